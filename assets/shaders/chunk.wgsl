@@ -0,0 +1,49 @@
+// Chunk fragment shader: the standard PBR shader with the block atlas multiplied into the base color.
+// uv is the face position in blocks, uv_b the atlas origin of the face's tile. Taking the
+// fractional part of uv repeats the tile once per block, so greedy quads keep the per-block look.
+#import bevy_pbr::{
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::alpha_discard,
+}
+
+#ifdef PREPASS_PIPELINE
+#import bevy_pbr::{
+    prepass_io::{VertexOutput, FragmentOutput},
+    pbr_deferred_functions::deferred_output,
+}
+#else
+#import bevy_pbr::{
+    forward_io::{VertexOutput, FragmentOutput},
+    pbr_functions::{apply_pbr_lighting, main_pass_post_lighting_processing},
+}
+#endif
+
+@group(2) @binding(100) var<uniform> tile_size: f32;
+@group(2) @binding(101) var atlas_texture: texture_2d<f32>;
+@group(2) @binding(102) var atlas_sampler: sampler;
+
+@fragment
+fn fragment(
+    in: VertexOutput,
+    @builtin(front_facing) is_front: bool,
+) -> FragmentOutput {
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+
+#ifdef VERTEX_UVS_B
+    let atlas_uv = in.uv_b + fract(in.uv) * tile_size;
+    // No mipmaps, and fract jumps at block edges, so sample the top level explicitly
+    pbr_input.material.base_color *= textureSampleLevel(atlas_texture, atlas_sampler, atlas_uv, 0.0);
+#endif
+
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+#ifdef PREPASS_PIPELINE
+    let out = deferred_output(in, pbr_input);
+#else
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+#endif
+
+    return out;
+}
@@ -0,0 +1,139 @@
+#![allow(dead_code)]
+use crate::registry::BlockRegistry;
+use bevy::{
+    pbr::{ExtendedMaterial, MaterialExtension},
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{AsBindGroup, Extent3d, ShaderRef, TextureDimension, TextureFormat},
+        texture::{CompressedImageFormats, ImageSampler, ImageType},
+    },
+};
+use std::{fs, path::Path};
+
+pub const TEXTURES_DIR: &str = "assets/textures";
+// Width and height of every block texture, in pixels
+pub const TILE_SIZE: u32 = 16;
+
+// Chunk material: the standard PBR material with the block atlas sampled on top of the vertex colors
+pub type BlockMaterial = ExtendedMaterial<StandardMaterial, AtlasExtension>;
+
+#[derive(Asset, AsBindGroup, Reflect, Debug, Clone)]
+pub struct AtlasExtension {
+    // Width and height of one tile in atlas UV units
+    #[uniform(100)]
+    pub tile_size: f32,
+    #[texture(101)]
+    #[sampler(102)]
+    pub atlas: Handle<Image>,
+}
+
+impl MaterialExtension for AtlasExtension {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/chunk.wgsl".into()
+    }
+}
+
+// Tiles are laid out row by row in the smallest square grid that fits them
+pub fn tiles_per_row(tile_count: usize) -> u32 {
+    let mut per_row = 1;
+    while (per_row * per_row) < tile_count as u32 {
+        per_row += 1;
+    }
+    per_row
+}
+
+// Atlas UV of the tile's top left corner
+pub fn tile_origin(tile: u16, tile_count: usize) -> [f32; 2] {
+    let per_row = tiles_per_row(tile_count);
+    let column = tile as u32 % per_row;
+    let row = tile as u32 / per_row;
+    [column as f32 / per_row as f32, row as f32 / per_row as f32]
+}
+
+// Packs every texture named in the registry into one image, in the registry's tile order
+pub fn build_atlas_image(registry: &BlockRegistry) -> Image {
+    let tile_count = registry.tile_count();
+    let per_row = tiles_per_row(tile_count);
+    let atlas_size = per_row * TILE_SIZE;
+    let mut data = vec![0; (atlas_size * atlas_size * 4) as usize];
+
+    let mut tiles = vec![vec![255; (TILE_SIZE * TILE_SIZE * 4) as usize]];
+    for name in registry.texture_names() {
+        let path = Path::new(TEXTURES_DIR).join(format!("{}.png", name));
+        let tile = load_tile(&path).unwrap_or_else(|err| {
+            println!("Failed to load texture {}: {}", path.display(), err);
+            missing_tile()
+        });
+        tiles.push(tile);
+    }
+
+    let row_bytes = (TILE_SIZE * 4) as usize;
+    for (tile, pixels) in tiles.iter().enumerate() {
+        let tile_x = (tile as u32 % per_row) * TILE_SIZE;
+        let tile_y = (tile as u32 / per_row) * TILE_SIZE;
+        for y in 0..TILE_SIZE {
+            let src = y as usize * row_bytes;
+            let dst = (((tile_y + y) * atlas_size + tile_x) * 4) as usize;
+            data[dst..dst + row_bytes].copy_from_slice(&pixels[src..src + row_bytes]);
+        }
+    }
+
+    let mut image = Image::new(
+        Extent3d {
+            width: atlas_size,
+            height: atlas_size,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    // Nearest filtering keeps the pixel look and stops neighboring tiles bleeding in
+    image.sampler = ImageSampler::nearest();
+    image
+}
+
+// Reads a TILE_SIZE x TILE_SIZE png as RGBA8 pixels
+fn load_tile(path: &Path) -> Result<Vec<u8>, String> {
+    let bytes = fs::read(path).map_err(|err| err.to_string())?;
+    let image = Image::from_buffer(
+        &bytes,
+        ImageType::Extension("png"),
+        CompressedImageFormats::NONE,
+        true,
+        ImageSampler::Default,
+        RenderAssetUsages::default(),
+    )
+    .map_err(|err| err.to_string())?;
+    if image.width() != TILE_SIZE || image.height() != TILE_SIZE {
+        return Err(format!(
+            "expected {}x{} pixels, got {}x{}",
+            TILE_SIZE,
+            TILE_SIZE,
+            image.width(),
+            image.height()
+        ));
+    }
+    let image = image
+        .convert(TextureFormat::Rgba8UnormSrgb)
+        .ok_or_else(|| "unsupported pixel format".to_string())?;
+    Ok(image.data)
+}
+
+// Magenta and black checkerboard, hard to miss in the world
+fn missing_tile() -> Vec<u8> {
+    let mut pixels = Vec::with_capacity((TILE_SIZE * TILE_SIZE * 4) as usize);
+    for y in 0..TILE_SIZE {
+        for x in 0..TILE_SIZE {
+            let magenta = (x / 4 + y / 4) % 2 == 0;
+            if magenta {
+                pixels.extend([255, 0, 255, 255]);
+            } else {
+                pixels.extend([0, 0, 0, 255]);
+            }
+        }
+    }
+    pixels
+}
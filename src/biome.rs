@@ -0,0 +1,345 @@
+#![allow(dead_code)]
+use crate::{block::BlockType, settings::TerrainSettings, terrain::TerrainBlocks};
+use bevy::prelude::*;
+
+// Biomes are picked from three slowly changing noise maps sampled per column. Each value is
+// roughly in -1..1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Climate {
+    pub temperature: f32,
+    pub humidity: f32,
+    // Low values are open sea, high values inland
+    pub continentalness: f32,
+}
+
+// Something placed on top of a biome's surface. Chances are per surface column.
+#[derive(Debug, Clone)]
+pub enum Decoration {
+    // A straight stack of one block, like a cactus
+    Column {
+        block: BlockType,
+        chance: f32,
+        min_height: i32,
+        max_height: i32,
+    },
+    // Trunk with a round crown of leaves, the crown can reach into the neighboring chunks
+    Tree {
+        log: BlockType,
+        leaves: BlockType,
+        chance: f32,
+        min_height: i32,
+        max_height: i32,
+    },
+    // Lump of rock half sunk into the ground
+    Boulder {
+        block: BlockType,
+        chance: f32,
+        radius: i32,
+    },
+    // Fixed set of blocks, positioned relative to the block above the surface
+    Structure {
+        blocks: Vec<(IVec3, BlockType)>,
+        chance: f32,
+    },
+}
+
+impl Decoration {
+    pub fn chance(&self) -> f32 {
+        match *self {
+            Decoration::Column { chance, .. }
+            | Decoration::Tree { chance, .. }
+            | Decoration::Boulder { chance, .. }
+            | Decoration::Structure { chance, .. } => chance,
+        }
+    }
+}
+
+// How a biome's ground is hollowed out. Cheese caves are big open chambers where a 3D noise
+// rises above the threshold, tunnels are winding worms where two 3D noises both cross zero.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Caves {
+    // 1.0 or more switches chambers off
+    pub cheese_threshold: f32,
+    // 0.0 switches tunnels off
+    pub tunnel_width: f32,
+    // Blocks of ground below the surface left uncarved, 0 lets caves open at the surface
+    pub min_depth: i32,
+}
+
+impl Caves {
+    pub const NONE: Caves = Caves {
+        cheese_threshold: 1.0,
+        tunnel_width: 0.0,
+        min_depth: 0,
+    };
+}
+
+#[derive(Debug, Clone)]
+pub struct Biome {
+    pub name: &'static str,
+    // Where the biome sits in climate space, land biomes are picked by the closest match
+    pub temperature: f32,
+    pub humidity: f32,
+    // Height curve: base_height + height_variation * detail^sharpness, with detail in 0..1.
+    // Sharpness above 1 keeps most of the ground low and leaves the high detail values for peaks.
+    pub base_height: f32,
+    pub height_variation: f32,
+    pub sharpness: f32,
+    // Top block of the ground and the blocks below it, stone starts after subsurface_depth
+    pub surface: BlockType,
+    pub subsurface: BlockType,
+    pub subsurface_depth: i32,
+    // Water freezes over and beaches stay snowy
+    pub frozen: bool,
+    pub caves: Caves,
+    pub decorations: Vec<Decoration>,
+}
+
+impl Biome {
+    pub fn height(&self, detail: f32) -> f32 {
+        self.base_height + self.height_variation * detail.clamp(0.0, 1.0).powf(self.sharpness)
+    }
+}
+
+pub const OCEAN: usize = 0;
+
+// Climate distance over which neighboring land biomes fade into each other
+const BLEND_WIDTH: f32 = 0.12;
+// Continentalness range of the coast, the height fades from ocean floor to land across it
+const COAST_START: f32 = -0.35;
+const COAST_END: f32 = -0.1;
+
+// Result of sampling the biomes for one column
+#[derive(Debug, Clone, Copy)]
+pub struct BiomeSample {
+    // Biome whose blocks and decorations the column uses
+    pub biome: usize,
+    pub height: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct BiomeSet {
+    // OCEAN first, then the land biomes
+    pub biomes: Vec<Biome>,
+}
+
+impl BiomeSet {
+    pub fn new(blocks: &TerrainBlocks, settings: &TerrainSettings) -> Self {
+        let sea_level = settings.sea_level as f32;
+        let biomes = vec![
+            Biome {
+                name: "ocean",
+                temperature: 0.0,
+                humidity: 0.0,
+                base_height: sea_level - 20.0,
+                height_variation: 14.0,
+                sharpness: 1.0,
+                surface: blocks.sand,
+                subsurface: blocks.gravel,
+                subsurface_depth: 3,
+                frozen: false,
+                caves: Caves {
+                    cheese_threshold: 0.55,
+                    tunnel_width: 0.06,
+                    min_depth: 8,
+                },
+                decorations: Vec::new(),
+            },
+            Biome {
+                name: "plains",
+                temperature: 0.15,
+                humidity: 0.0,
+                base_height: sea_level + 3.0,
+                height_variation: 12.0,
+                sharpness: 1.0,
+                surface: blocks.grass,
+                subsurface: blocks.dirt,
+                subsurface_depth: settings.dirt_depth,
+                frozen: false,
+                caves: Caves {
+                    cheese_threshold: 0.45,
+                    tunnel_width: 0.08,
+                    min_depth: 0,
+                },
+                decorations: vec![
+                    Decoration::Tree {
+                        log: blocks.log,
+                        leaves: blocks.leaves,
+                        chance: 0.004,
+                        min_height: 4,
+                        max_height: 6,
+                    },
+                    Decoration::Boulder {
+                        block: blocks.stone,
+                        chance: 0.001,
+                        radius: 2,
+                    },
+                ],
+            },
+            Biome {
+                name: "forest",
+                temperature: 0.05,
+                humidity: 0.35,
+                base_height: sea_level + 5.0,
+                height_variation: 20.0,
+                sharpness: 1.2,
+                surface: blocks.grass,
+                subsurface: blocks.dirt,
+                subsurface_depth: settings.dirt_depth,
+                frozen: false,
+                caves: Caves {
+                    cheese_threshold: 0.45,
+                    tunnel_width: 0.08,
+                    min_depth: 0,
+                },
+                decorations: vec![Decoration::Tree {
+                    log: blocks.log,
+                    leaves: blocks.leaves,
+                    chance: 0.04,
+                    min_height: 5,
+                    max_height: 7,
+                }],
+            },
+            Biome {
+                name: "desert",
+                temperature: 0.4,
+                humidity: -0.3,
+                base_height: sea_level + 3.0,
+                height_variation: 14.0,
+                sharpness: 1.5,
+                surface: blocks.sand,
+                subsurface: blocks.sandstone,
+                subsurface_depth: 6,
+                frozen: false,
+                caves: Caves {
+                    cheese_threshold: 0.5,
+                    tunnel_width: 0.07,
+                    min_depth: 0,
+                },
+                decorations: vec![
+                    Decoration::Column {
+                        block: blocks.cactus,
+                        chance: 0.006,
+                        min_height: 1,
+                        max_height: 3,
+                    },
+                    Decoration::Structure {
+                        blocks: desert_well(blocks),
+                        chance: 0.0002,
+                    },
+                ],
+            },
+            Biome {
+                name: "tundra",
+                temperature: -0.4,
+                humidity: 0.1,
+                base_height: sea_level + 4.0,
+                height_variation: 14.0,
+                sharpness: 1.0,
+                surface: blocks.snow,
+                subsurface: blocks.dirt,
+                subsurface_depth: settings.dirt_depth,
+                frozen: true,
+                caves: Caves {
+                    cheese_threshold: 0.5,
+                    tunnel_width: 0.08,
+                    min_depth: 0,
+                },
+                decorations: vec![Decoration::Column {
+                    block: blocks.ice,
+                    chance: 0.002,
+                    min_height: 2,
+                    max_height: 6,
+                }],
+            },
+            Biome {
+                name: "mountains",
+                temperature: -0.15,
+                humidity: -0.35,
+                base_height: sea_level + 15.0,
+                height_variation: 150.0,
+                sharpness: 2.0,
+                surface: blocks.stone,
+                subsurface: blocks.stone,
+                subsurface_depth: 1,
+                frozen: false,
+                caves: Caves {
+                    cheese_threshold: 0.35,
+                    tunnel_width: 0.1,
+                    min_depth: 0,
+                },
+                decorations: Vec::new(),
+            },
+        ];
+        Self { biomes }
+    }
+
+    pub fn get(&self, biome: usize) -> &Biome {
+        &self.biomes[biome]
+    }
+
+    // Picks the column's biome and blends the height curves of the biomes around its climate,
+    // so heights change smoothly across biome borders while blocks switch at the border.
+    pub fn sample(&self, climate: Climate, detail: f32) -> BiomeSample {
+        let distances: Vec<f32> = self
+            .biomes
+            .iter()
+            .map(|biome| {
+                let dt = climate.temperature - biome.temperature;
+                let dh = climate.humidity - biome.humidity;
+                dt * dt + dh * dh
+            })
+            .collect();
+        let mut closest = OCEAN + 1;
+        for index in OCEAN + 1..self.biomes.len() {
+            if distances[index] < distances[closest] {
+                closest = index;
+            }
+        }
+
+        let mut land_height = 0.0;
+        let mut total_weight = 0.0;
+        for index in OCEAN + 1..self.biomes.len() {
+            // Relative to the closest biome so the weights never all underflow to zero far
+            // from every biome center, the closest one always weighs 1
+            let weight =
+                (-(distances[index] - distances[closest]) / (BLEND_WIDTH * BLEND_WIDTH)).exp();
+            land_height += self.biomes[index].height(detail) * weight;
+            total_weight += weight;
+        }
+        let land_height = land_height / total_weight;
+
+        let ocean_height = self.biomes[OCEAN].height(detail);
+        let land = smoothstep(COAST_START, COAST_END, climate.continentalness);
+        BiomeSample {
+            biome: if land < 0.5 { OCEAN } else { closest },
+            height: ocean_height + (land_height - ocean_height) * land,
+        }
+    }
+}
+
+// Sandstone well with a roof on four pillars, 5x5 blocks
+fn desert_well(blocks: &TerrainBlocks) -> Vec<(IVec3, BlockType)> {
+    let mut well = Vec::new();
+    for x in -2..=2 {
+        for z in -2..=2 {
+            let corner = (x == -2 || x == 2) && (z == -2 || z == 2);
+            if x == 0 && z == 0 {
+                well.push((IVec3::new(x, 0, z), blocks.water));
+            } else if x.abs() <= 1 && z.abs() <= 1 {
+                well.push((IVec3::new(x, 0, z), blocks.sandstone));
+            } else if corner {
+                for y in 0..3 {
+                    well.push((IVec3::new(x, y, z), blocks.sandstone));
+                }
+            }
+            well.push((IVec3::new(x, 3, z), blocks.sandstone));
+        }
+    }
+    well
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
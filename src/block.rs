@@ -1,18 +1,30 @@
 #![allow(dead_code)]
+use crate::{
+    coords,
+    light::LightStorage,
+    palette::PaletteStorage,
+    registry::BlockRegistry,
+    settings::TerrainSettings,
+    terrain::{FeatureWrite, GeneratedChunk, TerrainGenerator},
+};
 use bevy::prelude::*;
-use bracket_noise::prelude::*;
 use rand::Rng;
 // use rayon::vec;
 
-use std::{collections::HashMap, fs::File, io::Write};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::Write,
+    sync::Arc,
+};
 
+// Chunks are cubes stacked in all three directions, so the world has no height limit
 pub const CHUNK_SIZE: i32 = 32;
-pub const CHUNK_HEIGHT: i32 = 64;
-pub const SEA_LEVEL: i32 = 30;
+pub const CHUNK_HEIGHT: i32 = 32;
 
 #[derive(Debug)]
 pub struct WorldMap {
-    pub map: HashMap<(i32, i32, i32), Voxel>,
+    pub map: HashMap<(i32, i32, i32), BlockType>,
 }
 
 impl WorldMap {
@@ -24,21 +36,19 @@ impl WorldMap {
 
     pub fn collect_voxels(&mut self, chunk_map: &ChunkMap) {
         for (chunk_pos, chunk) in &chunk_map.map {
-            for voxel in &chunk.voxels {
-                let x = voxel.id % CHUNK_SIZE;
-                let y = (voxel.id / CHUNK_SIZE) % CHUNK_HEIGHT;
-                let z = voxel.id / (CHUNK_SIZE * CHUNK_HEIGHT);
-                let world_pos = (
-                    chunk_pos.x * CHUNK_SIZE + x,
-                    chunk_pos.y * CHUNK_HEIGHT + y,
-                    chunk_pos.z * CHUNK_SIZE + z,
-                );
-                self.map.insert(world_pos, *voxel);
+            for (index, local_pos) in coords::local_positions().enumerate() {
+                let voxel = match chunk.get_voxel_by_index(index) {
+                    Some(voxel) => voxel,
+                    None => continue,
+                };
+                let world_pos = coords::local_to_world(*chunk_pos, local_pos);
+                self.map
+                    .insert((world_pos.x, world_pos.y, world_pos.z), voxel);
             }
         }
     }
 
-    fn get_voxel(&self, x: i32, y: i32, z: i32) -> Option<&Voxel> {
+    fn get_voxel(&self, x: i32, y: i32, z: i32) -> Option<&BlockType> {
         self.map.get(&(x, y, z))
     }
 }
@@ -53,178 +63,461 @@ impl AsVec3 for IVec3 {
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
-pub enum BlockType {
-    Air,
-    Stone,
-    Dirt,
-    Grass,
-    Snow,
-    Water,
-}
+// Id of a block type in the BlockRegistry, which holds its name and properties
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct BlockType(pub u16);
 
 impl BlockType {
-    pub fn color(&self) -> [f32; 4] {
-        match self {
-            BlockType::Air => [0.0, 0.0, 0.0, 0.0],
-            BlockType::Stone => [0.5, 0.5, 0.5, 1.0],
-            BlockType::Dirt => [0.5, 0.25, 0.0, 1.0],
-            BlockType::Grass => [0.0, 0.5, 0.0, 1.0],
-            BlockType::Snow => [1.0, 1.0, 1.0, 1.0],
-            BlockType::Water => [0.0, 0.0, 1.0, 0.5],
-        }
-    }
+    // Air is always registered first
+    pub const AIR: BlockType = BlockType(0);
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct Voxel {
-    pub id: i32,
-    pub is_solid: bool,
-    pub block_type: BlockType,
-}
+pub const CHUNK_VOLUME: usize = (CHUNK_SIZE * CHUNK_HEIGHT * CHUNK_SIZE) as usize;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Chunk {
-    pub voxels: Vec<Voxel>,
+    blocks: PaletteStorage,
+    // None until the chunk is lit, see light::light_chunk. Not saved, it's worked out again on load.
+    light: Option<LightStorage>,
 }
 
 impl Chunk {
+    // Blocks are expected in storage order, see coords::local_positions
+    pub fn from_blocks(blocks: &[BlockType]) -> Self {
+        Self {
+            blocks: PaletteStorage::from_blocks(blocks),
+            light: None,
+        }
+    }
+
+    // A chunk made of a single block type, e.g. all air above the terrain
+    pub fn uniform(block_type: BlockType) -> Self {
+        Self {
+            blocks: PaletteStorage::uniform(CHUNK_VOLUME, block_type),
+            light: None,
+        }
+    }
+
+    pub fn from_storage(blocks: PaletteStorage) -> Self {
+        Self {
+            blocks,
+            light: None,
+        }
+    }
+
+    pub fn storage(&self) -> &PaletteStorage {
+        &self.blocks
+    }
+
     pub fn get_size(&self) -> i32 {
-        self.voxels.len() as i32
+        self.blocks.len() as i32
     }
 
-    fn rendered_voxels(&self) -> Vec<&Voxel> {
-        self.voxels.iter().filter(|v| v.is_solid).collect()
+    pub fn is_uniform(&self) -> bool {
+        self.blocks.is_uniform()
     }
 
-    pub fn rendered_voxels_count(&self) -> i32 {
-        self.rendered_voxels().len() as i32
+    pub fn rendered_voxels_count(&self, registry: &BlockRegistry) -> i32 {
+        if let PaletteStorage::Uniform { len, block_type } = self.blocks {
+            return if registry.is_solid(block_type) {
+                len as i32
+            } else {
+                0
+            };
+        }
+        (0..self.blocks.len())
+            .filter(|index| {
+                self.get_voxel_by_index(*index)
+                    .is_some_and(|block_type| registry.is_solid(block_type))
+            })
+            .count() as i32
+    }
+
+    pub fn get_voxel_by_index(&self, index: usize) -> Option<BlockType> {
+        self.blocks.get(index)
+    }
+
+    pub fn get_voxel(&self, x: i32, y: i32, z: i32) -> Option<BlockType> {
+        self.get_voxel_by_index(coords::local_to_index(IVec3::new(x, y, z))?)
+    }
+
+    // Returns false if the position is outside the chunk
+    pub fn set_voxel(&mut self, x: i32, y: i32, z: i32, block_type: BlockType) -> bool {
+        match coords::local_to_index(IVec3::new(x, y, z)) {
+            Some(index) => self.blocks.set(index, block_type),
+            None => false,
+        }
     }
 
-    pub fn get_voxel(&self, x: i32, y: i32, z: i32) -> Option<&Voxel> {
-        if x < 0 || x >= CHUNK_SIZE || y < 0 || y >= CHUNK_HEIGHT || z < 0 || z >= CHUNK_SIZE {
+    pub fn light(&self) -> Option<&LightStorage> {
+        self.light.as_ref()
+    }
+
+    pub fn set_light(&mut self, light: LightStorage) {
+        self.light = Some(light);
+    }
+
+    // Packed sky and block light of a voxel, see light::pack_light. None if the chunk isn't lit.
+    pub fn get_light(&self, local_pos: IVec3) -> Option<u8> {
+        Some(self.light.as_ref()?.get(coords::local_to_index(local_pos)?))
+    }
+
+    // Returns false if the chunk isn't lit or the position is outside it
+    pub fn set_voxel_light(&mut self, local_pos: IVec3, light: u8) -> bool {
+        match (self.light.as_mut(), coords::local_to_index(local_pos)) {
+            (Some(storage), Some(index)) => {
+                storage.set(index, light);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Bytes used by the voxel storage of this chunk
+    pub fn memory_usage(&self) -> usize {
+        self.blocks.memory_usage()
+    }
+
+    pub fn palette_len(&self) -> usize {
+        self.blocks.palette().len()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryStats {
+    pub chunks: usize,
+    pub uniform_chunks: usize,
+    // Bytes actually used by the palette storage
+    pub used_bytes: usize,
+    // Bytes the same chunks took as a flat Vec<Voxel> before the palette storage
+    pub unpacked_bytes: usize,
+}
+
+// Voxel of the old per-voxel chunk layout, only kept to measure the palette storage against
+struct UnpackedVoxel {
+    id: i32,
+    is_solid: bool,
+    // The old fieldless BlockType enum, a single byte
+    block_type: u8,
+}
+
+// A chunk together with the 26 chunks around it, cheap to clone into a worker thread
+#[derive(Debug, Clone)]
+pub struct ChunkNeighborhood {
+    pub chunk_pos: IVec3,
+    chunks: [Option<Arc<Chunk>>; 27],
+}
+
+impl ChunkNeighborhood {
+    fn slot(offset: IVec3) -> usize {
+        ((offset.x + 1) * 9 + (offset.y + 1) * 3 + (offset.z + 1)) as usize
+    }
+
+    fn slot_offset(slot: usize) -> IVec3 {
+        let slot = slot as i32;
+        IVec3::new(slot / 9 - 1, (slot / 3) % 3 - 1, slot % 3 - 1)
+    }
+
+    pub fn center(&self) -> &Chunk {
+        self.chunks[Self::slot(IVec3::ZERO)]
+            .as_deref()
+            .expect("neighborhood is only built around loaded chunks")
+    }
+
+    // Looks up a voxel relative to the center chunk's origin, reaching up to one chunk past each border
+    pub fn get_voxel(&self, local_pos: IVec3) -> Option<BlockType> {
+        let offset = coords::world_to_chunk(local_pos);
+        if offset.abs().max_element() > 1 {
             return None;
         }
+        let chunk = self.chunks[Self::slot(offset)].as_ref()?;
+        let inner_pos = coords::world_to_local(local_pos);
+        chunk.get_voxel(inner_pos.x, inner_pos.y, inner_pos.z)
+    }
 
-        let index = (x * CHUNK_HEIGHT * CHUNK_SIZE + y * CHUNK_SIZE + z) as usize;
-        return self.voxels.get(index);
+    // The same neighborhood with every chunk passed through f, given the chunk's offset from the
+    // center. Chunks f returns None for are left out, as if they weren't loaded.
+    pub fn map_chunks(&self, f: impl Fn(IVec3, &Chunk) -> Option<Chunk>) -> ChunkNeighborhood {
+        let chunks = std::array::from_fn(|slot| {
+            let chunk = self.chunks[slot].as_ref()?;
+            f(Self::slot_offset(slot), chunk).map(Arc::new)
+        });
+        ChunkNeighborhood {
+            chunk_pos: self.chunk_pos,
+            chunks,
+        }
+    }
+
+    // Light of a voxel relative to the center chunk's origin, like get_voxel
+    pub fn get_light(&self, local_pos: IVec3) -> Option<u8> {
+        let offset = coords::world_to_chunk(local_pos);
+        if offset.abs().max_element() > 1 {
+            return None;
+        }
+        let chunk = self.chunks[Self::slot(offset)].as_ref()?;
+        chunk.get_light(coords::world_to_local(local_pos))
     }
 }
 
 #[derive(Debug, Resource)]
 pub struct ChunkMap {
-    pub map: HashMap<IVec3, Chunk>,
+    // Chunks are shared with the worker threads meshing them, edits go through Arc::make_mut
+    pub map: HashMap<IVec3, Arc<Chunk>>,
     pub seed: u64,
+    // Chunks whose mesh no longer matches their voxels
+    pub dirty: HashSet<IVec3>,
+    // Chunks edited since they were generated or last saved
+    pub modified: HashSet<IVec3>,
+    // Decoration blocks each loaded chunk placed in the chunks around it, by the chunk that
+    // generated them. Neighbors generated later pick them up, and they go away with their chunk.
+    pub feature_writes: HashMap<IVec3, Vec<FeatureWrite>>,
+    // Loaded chunks whose voxels came from or went to the save file. They already hold their
+    // decorations, writing them again would regrow blocks the player broke.
+    pub saved: HashSet<IVec3>,
+    // Chunks and changed blocks the light hasn't caught up with yet, see light::update_light
+    pub unlit_chunks: Vec<IVec3>,
+    pub light_edits: Vec<IVec3>,
 }
 
 impl ChunkMap {
     pub fn new() -> Self {
-        let seed = rand::thread_rng().gen();
+        Self::with_seed(rand::thread_rng().gen())
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
         Self {
             map: HashMap::new(),
             seed,
+            dirty: HashSet::new(),
+            modified: HashSet::new(),
+            feature_writes: HashMap::new(),
+            saved: HashSet::new(),
+            unlit_chunks: Vec::new(),
+            light_edits: Vec::new(),
         }
     }
 
     pub fn insert_chunk(&mut self, chunk_pos: IVec3, chunk: Chunk) {
-        self.map.insert(chunk_pos, chunk);
-    }
-
-    pub fn create_chunk_heightmap(&mut self, chunk_pos: IVec3) -> Vec<i32> {
-        let mut heightmap: Vec<i32> = Vec::with_capacity((CHUNK_SIZE * CHUNK_SIZE) as usize); // vector preallocation
-        let mut noise: FastNoise = FastNoise::seeded(self.seed);
-        noise.set_noise_type(NoiseType::Simplex);
-        noise.set_frequency(0.3);
-
-        for z in 0..CHUNK_SIZE {
-            for x in 0..CHUNK_SIZE {
-                // Get voxel X and Z position in global space
-                let voxel_x = chunk_pos.x * CHUNK_SIZE + x;
-                let voxel_z = chunk_pos.z * CHUNK_SIZE + z;
-                let noise_value1 =
-                    noise.get_noise(voxel_x as f32 / 16.0, voxel_z as f32 / 16.0) * 0.5;
-                let noise_value2 =
-                    noise.get_noise(voxel_x as f32 / 32.0, voxel_z as f32 / 32.0) * 0.25;
-                let noise_value3 =
-                    noise.get_noise(voxel_x as f32 / 64.0, voxel_z as f32 / 64.0) * 0.25;
-
-                let noise_value = noise_value1 + noise_value2 + noise_value3;
-                //println!("Noise Value: {}", noise_value);
-                let normalized_noise_value = (noise_value + 1.0) / 2.0;
-                let scaled_noise_value = normalized_noise_value * 64.0;
-                let final_noise_value = scaled_noise_value as i32;
-                // Apply to heightmap
-                heightmap.push(final_noise_value);
+        self.map.insert(chunk_pos, Arc::new(chunk));
+        self.unlit_chunks.push(chunk_pos);
+    }
+
+    // Adds a chunk fresh from the generator or the save file and hands its spill on to the
+    // chunks around it. Generated chunks also get the decoration blocks their loaded neighbors
+    // placed in them.
+    pub fn insert_generated(&mut self, chunk_pos: IVec3, generated: GeneratedChunk) {
+        self.insert_chunk(chunk_pos, generated.chunk);
+        if generated.from_save {
+            self.saved.insert(chunk_pos);
+        } else {
+            let writes: Vec<FeatureWrite> = (0..27)
+                .map(|slot| chunk_pos + ChunkNeighborhood::slot_offset(slot))
+                .filter_map(|source| self.feature_writes.get(&source))
+                .flatten()
+                .filter(|write| coords::world_to_chunk(write.world_pos) == chunk_pos)
+                .copied()
+                .collect();
+            for write in writes {
+                self.apply_feature_write(write);
+            }
+        }
+        self.write_features(chunk_pos, generated.spill);
+    }
+
+    // Records the decoration blocks a chunk placed outside of itself and writes them into the
+    // loaded chunks that take them, the rest are written when their chunk is generated
+    pub fn write_features(&mut self, source: IVec3, writes: Vec<FeatureWrite>) {
+        for write in &writes {
+            self.apply_feature_write(*write);
+        }
+        self.feature_writes.insert(source, writes);
+    }
+
+    // Whether decorations may still be written into a chunk: it is loaded, came from the
+    // generator and wasn't edited since
+    fn takes_features(&self, chunk_pos: IVec3) -> bool {
+        self.map.contains_key(&chunk_pos)
+            && !self.saved.contains(&chunk_pos)
+            && !self.modified.contains(&chunk_pos)
+    }
+
+    // Part of generating the world rather than an edit, so the chunk isn't marked modified
+    fn apply_feature_write(&mut self, write: FeatureWrite) {
+        if !self.takes_features(coords::world_to_chunk(write.world_pos)) {
+            return;
+        }
+        if let Some(current) = self.get_block(write.world_pos) {
+            if write.applies_to(current) {
+                self.write_block(write.world_pos, write.block);
+            }
+        }
+    }
+
+    pub fn remove_chunk(&mut self, chunk_pos: IVec3) -> Option<Arc<Chunk>> {
+        self.dirty.remove(&chunk_pos);
+        self.modified.remove(&chunk_pos);
+        self.saved.remove(&chunk_pos);
+        self.feature_writes.remove(&chunk_pos);
+        // Waiting for light would only take a place in the per frame limit, see light::update_light
+        self.unlit_chunks
+            .retain(|unlit_pos| *unlit_pos != chunk_pos);
+        self.map.remove(&chunk_pos)
+    }
+
+    // Flags a chunk for remeshing if it is loaded
+    pub fn mark_dirty(&mut self, chunk_pos: IVec3) {
+        if self.map.contains_key(&chunk_pos) {
+            self.dirty.insert(chunk_pos);
+        }
+    }
+
+    pub fn get_chunk(&self, chunk_pos: IVec3) -> Option<&Chunk> {
+        self.map.get(&chunk_pos).map(|chunk| chunk.as_ref())
+    }
+
+    // Snapshot of a chunk and the chunks around it, None if the chunk itself isn't loaded
+    pub fn neighborhood(&self, chunk_pos: IVec3) -> Option<ChunkNeighborhood> {
+        self.map.get(&chunk_pos)?;
+        let chunks = std::array::from_fn(|slot| {
+            let offset = ChunkNeighborhood::slot_offset(slot);
+            self.map.get(&(chunk_pos + offset)).cloned()
+        });
+        Some(ChunkNeighborhood { chunk_pos, chunks })
+    }
+
+    // Block at a world position, crossing into whichever chunk owns it.
+    // None if that chunk isn't loaded.
+    pub fn get_block(&self, world_pos: IVec3) -> Option<BlockType> {
+        let chunk = self.map.get(&coords::world_to_chunk(world_pos))?;
+        let local_pos = coords::world_to_local(world_pos);
+        chunk.get_voxel(local_pos.x, local_pos.y, local_pos.z)
+    }
+
+    // Changes the block at a world position and flags the chunks whose mesh can see it.
+    // Returns false if the chunk owning the position isn't loaded.
+    pub fn set_block(&mut self, world_pos: IVec3, block_type: BlockType) -> bool {
+        match self.write_block(world_pos, block_type) {
+            Some(true) => {
+                self.modified.insert(coords::world_to_chunk(world_pos));
+                true
             }
+            Some(false) => true,
+            None => false,
+        }
+    }
+
+    // set_block without marking the chunk modified. Returns whether the block changed, or None
+    // if the chunk isn't loaded.
+    fn write_block(&mut self, world_pos: IVec3, block_type: BlockType) -> Option<bool> {
+        let chunk_pos = coords::world_to_chunk(world_pos);
+        let local_pos = coords::world_to_local(world_pos);
+        let chunk = self.map.get_mut(&chunk_pos)?;
+        if chunk.get_voxel(local_pos.x, local_pos.y, local_pos.z) == Some(block_type) {
+            return Some(false);
         }
+        // Clones the chunk if a meshing task still holds the old version
+        Arc::make_mut(chunk).set_voxel(local_pos.x, local_pos.y, local_pos.z, block_type);
+        self.light_edits.push(world_pos);
+        self.mark_voxel_dirty(world_pos);
+        Some(true)
+    }
+
+    // Packed light at a world position, None if its chunk isn't loaded or not lit yet
+    pub fn get_light(&self, world_pos: IVec3) -> Option<u8> {
+        let chunk = self.map.get(&coords::world_to_chunk(world_pos))?;
+        chunk.get_light(coords::world_to_local(world_pos))
+    }
 
-        return heightmap;
-    }
-
-    pub fn create_chunk_voxels(&mut self, chunk_pos: IVec3, heightmap: Vec<i32>) -> Vec<Voxel> {
-        let mut voxels: Vec<Voxel> =
-            Vec::with_capacity((CHUNK_SIZE * CHUNK_HEIGHT * CHUNK_SIZE) as usize); // vector preallocation
-
-        for z in 0..CHUNK_SIZE {
-            for x in 0..CHUNK_SIZE {
-                let heightmap_index = (x * CHUNK_SIZE + z) as usize;
-                for y in 0..CHUNK_HEIGHT {
-                    let voxel_id = x * CHUNK_HEIGHT * CHUNK_SIZE + y * CHUNK_SIZE + z;
-                    let voxel_y = chunk_pos.y * CHUNK_HEIGHT + y;
-                    let heightmap_value = heightmap[heightmap_index];
-
-                    // let is_solid = voxel_y <= heightmap_value;
-
-                    let block_type = if voxel_y >= 40 && voxel_y <= heightmap_value {
-                        BlockType::Snow
-                    } else if voxel_y == heightmap_value && voxel_y <= heightmap_value {
-                        BlockType::Grass
-                    } else if voxel_y > heightmap_value - 10 && voxel_y <= heightmap_value {
-                        BlockType::Dirt
-                    } else if voxel_y > 0 && voxel_y <= heightmap_value {
-                        BlockType::Stone
-                    } else if voxel_y <= SEA_LEVEL && voxel_y > heightmap_value {
-                        BlockType::Water
-                    } else {
-                        BlockType::Air
-                    };
-
-                    let is_solid = match block_type {
-                        BlockType::Air => false,
-                        _ => true,
-                    };
-
-                    let voxel = Voxel {
-                        id: voxel_id,
-                        is_solid,
-                        block_type,
-                    };
-                    voxels.push(voxel);
+    // Changes the light at a world position and flags the meshes showing it
+    pub fn set_light(&mut self, world_pos: IVec3, light: u8) -> bool {
+        let chunk_pos = coords::world_to_chunk(world_pos);
+        let local_pos = coords::world_to_local(world_pos);
+        let chunk = match self.map.get_mut(&chunk_pos) {
+            Some(chunk) => chunk,
+            None => return false,
+        };
+        match chunk.get_light(local_pos) {
+            Some(current) if current == light => return true,
+            Some(_) => {}
+            None => return false,
+        }
+        Arc::make_mut(chunk).set_voxel_light(local_pos, light);
+        self.mark_voxel_dirty(world_pos);
+        true
+    }
+
+    // Flags the chunk owning a voxel for remeshing, and the chunks next to it when the voxel
+    // is on the border, since their meshes can see it too
+    pub fn mark_voxel_dirty(&mut self, world_pos: IVec3) {
+        let chunk_pos = coords::world_to_chunk(world_pos);
+        let local_pos = coords::world_to_local(world_pos);
+        let size = coords::CHUNK_EXTENT;
+        let border_offsets = |axis: usize| -> Vec<i32> {
+            let mut offsets = vec![0];
+            if local_pos[axis] == 0 {
+                offsets.push(-1);
+            }
+            if local_pos[axis] == size[axis] - 1 {
+                offsets.push(1);
+            }
+            offsets
+        };
+        for x in border_offsets(0) {
+            for y in border_offsets(1) {
+                for z in border_offsets(2) {
+                    self.mark_dirty(chunk_pos + IVec3::new(x, y, z));
                 }
             }
         }
+    }
+
+    pub fn memory_stats(&self) -> MemoryStats {
+        let mut stats = MemoryStats::default();
+        for chunk in self.map.values() {
+            stats.chunks += 1;
+            if chunk.is_uniform() {
+                stats.uniform_chunks += 1;
+            }
+            stats.used_bytes += chunk.memory_usage();
+            stats.unpacked_bytes +=
+                chunk.get_size() as usize * std::mem::size_of::<UnpackedVoxel>();
+        }
+        stats
+    }
 
-        return voxels;
+    // Generator owning everything needed to build chunks for this map, safe to move to a worker thread
+    pub fn generator(
+        &self,
+        registry: &BlockRegistry,
+        settings: &TerrainSettings,
+    ) -> TerrainGenerator {
+        TerrainGenerator::new(self.seed, registry, settings)
     }
 
-    pub fn generate_chunk(&mut self, chunk_pos: IVec3) -> Chunk {
-        let heightmap = self.create_chunk_heightmap(chunk_pos);
-        // println!("Heightmap: {:?}", heightmap);
-        let voxels = self.create_chunk_voxels(chunk_pos, heightmap);
-        let chunk = Chunk { voxels };
-        return chunk;
+    pub fn generate_chunk(
+        &self,
+        chunk_pos: IVec3,
+        registry: &BlockRegistry,
+        settings: &TerrainSettings,
+    ) -> Chunk {
+        self.generator(registry, settings).generate_chunk(chunk_pos)
     }
 
-    pub fn generate_terrain(&mut self, world_size: IVec3) {
+    pub fn generate_terrain(
+        &mut self,
+        world_size: IVec3,
+        registry: &BlockRegistry,
+        settings: &TerrainSettings,
+    ) {
         let mut solid_voxels: i32 = 0;
         // println!("{}", self.seed);
         for z in 0..world_size.z {
             for x in 0..world_size.x {
                 for y in 0..world_size.y {
                     let chunk_pos: IVec3 = IVec3::new(x, y, z);
-                    let chunk = self.generate_chunk(chunk_pos);
-                    solid_voxels += chunk.rendered_voxels_count();
-                    self.insert_chunk(chunk_pos, chunk);
+                    let generated = self.generator(registry, settings).generate(chunk_pos);
+                    solid_voxels += generated.chunk.rendered_voxels_count(registry);
+                    self.insert_generated(chunk_pos, generated);
                 }
             }
         }
@@ -234,13 +527,14 @@ impl ChunkMap {
     }
 }
 
-pub fn collect_terrain_data(chunk_map: &ChunkMap) {
+pub fn collect_terrain_data(chunk_map: &ChunkMap, registry: &BlockRegistry) {
     let mut world_map = WorldMap::new();
     world_map.collect_voxels(chunk_map);
-    let terrain_map: HashMap<(i32, i32, i32), Voxel> = world_map.map;
+    let terrain_map: HashMap<(i32, i32, i32), BlockType> = world_map.map;
     // Write terrain data to a file
     let mut file: File = File::create("terrain_map.txt").expect("Unable to create file");
-    for ((x, y, z), voxel) in &terrain_map {
-        writeln!(file, "{},{},{},{}", x, y, z, voxel.is_solid).expect("Unable to write data");
+    for ((x, y, z), block_type) in &terrain_map {
+        let is_solid = registry.is_solid(*block_type);
+        writeln!(file, "{},{},{},{}", x, y, z, is_solid).expect("Unable to write data");
     }
 }
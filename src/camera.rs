@@ -14,6 +14,8 @@ pub struct FlyCamera {
     pitch: f32,
     transform: Transform,
     enabled: bool,
+    // Noclip flying, the player controller moves the camera when this is off
+    flying: bool,
 }
 
 impl Default for FlyCamera {
@@ -22,7 +24,7 @@ impl Default for FlyCamera {
             transform: Transform::from_xyz(0.0, 150.0, -50.0).looking_at(Vec3::ZERO, Vec3::Y),
             sensitivity: 0.2,
             speed: 0.5,
-            position: Vec3::new(3.0, 10.0, 10.0),
+            position: Vec3::new(3.0, 100.0, 10.0),
             front: Vec3::new(-0.3, -1.0, -1.0).normalize(),
             up: Vec3::new(0.0, 1.0, 0.0),
             right: Vec3::new(1.0, 0.0, 0.0),
@@ -30,6 +32,7 @@ impl Default for FlyCamera {
             yaw: -90.0,
             pitch: 0.0,
             enabled: true,
+            flying: true,
         }
     }
 }
@@ -46,6 +49,26 @@ impl FlyCamera {
     pub fn get_position(&self) -> Vec3 {
         self.position
     }
+
+    pub fn get_front(&self) -> Vec3 {
+        self.front
+    }
+
+    pub fn get_right(&self) -> Vec3 {
+        self.right
+    }
+
+    pub fn set_position(&mut self, position: Vec3) {
+        self.position = position;
+    }
+
+    pub fn is_flying(&self) -> bool {
+        self.flying
+    }
+
+    pub fn set_flying(&mut self, flying: bool) {
+        self.flying = flying;
+    }
 }
 
 pub fn process_keyboard(
@@ -54,6 +77,9 @@ pub fn process_keyboard(
     time: Res<Time>,
 ) {
     for mut camera in query.iter_mut() {
+        if !camera.flying {
+            continue;
+        }
         let mut direction = Vec3::ZERO;
         let delta_time = time.delta_seconds();
 
@@ -89,12 +115,7 @@ pub fn process_mouse(mut query: Query<&mut FlyCamera>, mut mouse_motion: EventRe
             camera.yaw += motion.delta.x * camera.sensitivity;
             camera.pitch -= motion.delta.y * camera.sensitivity;
         }
-        if camera.pitch > 89.0 {
-            camera.pitch = 89.0;
-        }
-        if camera.pitch < -89.0 {
-            camera.pitch = -89.0;
-        }
+        camera.pitch = camera.pitch.clamp(-89.0, 89.0);
         update_camera_vectors(&mut camera)
     }
 }
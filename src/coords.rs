@@ -0,0 +1,176 @@
+#![allow(dead_code)]
+use crate::block::{CHUNK_HEIGHT, CHUNK_SIZE, CHUNK_VOLUME};
+use bevy::prelude::*;
+
+// Conversions between the position spaces of the world:
+//   world: voxel position in the whole world
+//   chunk: position of a chunk in chunk units
+//   local: voxel position inside its chunk, 0..CHUNK_SIZE x 0..CHUNK_HEIGHT x 0..CHUNK_SIZE
+//   index: position of a local voxel in chunk storage
+//   column: position of an (x, z) local column in a chunk heightmap
+// Everything that lays out or walks chunk data goes through these so they all agree.
+
+// Size of a chunk in voxels along each axis
+pub const CHUNK_EXTENT: IVec3 = IVec3::new(CHUNK_SIZE, CHUNK_HEIGHT, CHUNK_SIZE);
+pub const CHUNK_COLUMNS: usize = (CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+pub fn is_local(local_pos: IVec3) -> bool {
+    local_pos.cmpge(IVec3::ZERO).all() && local_pos.cmplt(CHUNK_EXTENT).all()
+}
+
+// Storage order is x-major, then y, then z: z neighbors are adjacent in memory
+pub fn local_to_index(local_pos: IVec3) -> Option<usize> {
+    if !is_local(local_pos) {
+        return None;
+    }
+    Some(
+        (local_pos.x * CHUNK_HEIGHT * CHUNK_SIZE + local_pos.y * CHUNK_SIZE + local_pos.z) as usize,
+    )
+}
+
+pub fn index_to_local(index: usize) -> Option<IVec3> {
+    if index >= CHUNK_VOLUME {
+        return None;
+    }
+    let index = index as i32;
+    Some(IVec3::new(
+        index / (CHUNK_HEIGHT * CHUNK_SIZE),
+        (index / CHUNK_SIZE) % CHUNK_HEIGHT,
+        index % CHUNK_SIZE,
+    ))
+}
+
+// Every local position in storage order, so pushing one block per position builds a chunk
+pub fn local_positions() -> impl Iterator<Item = IVec3> {
+    (0..CHUNK_VOLUME).map(|index| index_to_local(index).unwrap())
+}
+
+// Chunk owning the voxel at a world position, negative positions round towards -infinity
+pub fn world_to_chunk(world_pos: IVec3) -> IVec3 {
+    world_pos.div_euclid(CHUNK_EXTENT)
+}
+
+// Position of a world voxel inside its chunk
+pub fn world_to_local(world_pos: IVec3) -> IVec3 {
+    world_pos.rem_euclid(CHUNK_EXTENT)
+}
+
+// World position of the chunk's origin voxel
+pub fn chunk_origin(chunk_pos: IVec3) -> IVec3 {
+    chunk_pos * CHUNK_EXTENT
+}
+
+pub fn local_to_world(chunk_pos: IVec3, local_pos: IVec3) -> IVec3 {
+    chunk_origin(chunk_pos) + local_pos
+}
+
+// Chunk containing the given world space point
+pub fn point_to_chunk(point: Vec3) -> IVec3 {
+    world_to_chunk(point.floor().as_ivec3())
+}
+
+// Heightmaps follow the voxel storage order: x-major, then z
+pub fn column_index(x: i32, z: i32) -> Option<usize> {
+    if !(0..CHUNK_SIZE).contains(&x) || !(0..CHUNK_SIZE).contains(&z) {
+        return None;
+    }
+    Some((x * CHUNK_SIZE + z) as usize)
+}
+
+pub fn index_to_column(index: usize) -> Option<(i32, i32)> {
+    if index >= CHUNK_COLUMNS {
+        return None;
+    }
+    let index = index as i32;
+    Some((index / CHUNK_SIZE, index % CHUNK_SIZE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_round_trips_over_the_whole_chunk() {
+        let mut seen = vec![false; CHUNK_VOLUME];
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_HEIGHT {
+                for z in 0..CHUNK_SIZE {
+                    let local_pos = IVec3::new(x, y, z);
+                    let index = local_to_index(local_pos).unwrap();
+                    assert!(!seen[index], "{} shares index {}", local_pos, index);
+                    seen[index] = true;
+                    assert_eq!(index_to_local(index), Some(local_pos));
+                }
+            }
+        }
+        assert!(seen.iter().all(|seen| *seen));
+    }
+
+    #[test]
+    fn local_positions_follow_storage_order() {
+        for (index, local_pos) in local_positions().enumerate() {
+            assert_eq!(local_to_index(local_pos), Some(index));
+        }
+        assert_eq!(local_positions().count(), CHUNK_VOLUME);
+    }
+
+    #[test]
+    fn out_of_range_positions_have_no_index() {
+        for local_pos in [
+            IVec3::new(-1, 0, 0),
+            IVec3::new(0, -1, 0),
+            IVec3::new(0, 0, -1),
+            IVec3::new(CHUNK_SIZE, 0, 0),
+            IVec3::new(0, CHUNK_HEIGHT, 0),
+            IVec3::new(0, 0, CHUNK_SIZE),
+        ] {
+            assert_eq!(local_to_index(local_pos), None, "{}", local_pos);
+        }
+        assert_eq!(index_to_local(CHUNK_VOLUME), None);
+    }
+
+    #[test]
+    fn world_round_trips_through_chunk_and_local() {
+        // Chunks on both sides of the origin, so negative coordinates are covered
+        for chunk_x in -2..2 {
+            for chunk_y in -2..2 {
+                for chunk_z in -2..2 {
+                    let chunk_pos = IVec3::new(chunk_x, chunk_y, chunk_z);
+                    for local_pos in local_positions() {
+                        let world_pos = local_to_world(chunk_pos, local_pos);
+                        assert_eq!(world_to_chunk(world_pos), chunk_pos);
+                        assert_eq!(world_to_local(world_pos), local_pos);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn points_land_in_the_chunk_of_their_voxel() {
+        assert_eq!(
+            point_to_chunk(Vec3::new(-0.5, 0.5, 0.5)),
+            IVec3::new(-1, 0, 0)
+        );
+        assert_eq!(
+            point_to_chunk(Vec3::new(CHUNK_SIZE as f32, -0.01, 31.99)),
+            IVec3::new(1, -1, 0)
+        );
+    }
+
+    #[test]
+    fn column_index_round_trips() {
+        let mut seen = vec![false; CHUNK_COLUMNS];
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let index = column_index(x, z).unwrap();
+                assert!(!seen[index]);
+                seen[index] = true;
+                assert_eq!(index_to_column(index), Some((x, z)));
+            }
+        }
+        assert_eq!(column_index(CHUNK_SIZE, 0), None);
+        assert_eq!(column_index(0, -1), None);
+        assert_eq!(index_to_column(CHUNK_COLUMNS), None);
+    }
+}
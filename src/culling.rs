@@ -0,0 +1,278 @@
+#![allow(dead_code)]
+use crate::{
+    block::{BlockType, Chunk, ChunkMap, CHUNK_VOLUME},
+    camera::FlyCamera,
+    coords,
+    registry::BlockRegistry,
+    world::{ChunkEntities, ChunkMesh},
+};
+use bevy::prelude::*;
+use std::collections::{HashSet, VecDeque};
+
+// Cave culling: each chunk records which of its faces can see each other through its
+// transparent voxels, then a flood fill from the camera's chunk walks through those
+// connections. Chunks it never reaches are behind solid ground and get hidden.
+
+// Faces of a chunk, opposite faces are next to each other so face ^ 1 flips one
+pub const FACES: [IVec3; 6] = [
+    IVec3::X,
+    IVec3::NEG_X,
+    IVec3::Y,
+    IVec3::NEG_Y,
+    IVec3::Z,
+    IVec3::NEG_Z,
+];
+
+fn opposite(face: usize) -> usize {
+    face ^ 1
+}
+
+// Bit a * 6 + b is set when something could be seen through the chunk from face a to face b
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkConnectivity(u64);
+
+impl ChunkConnectivity {
+    pub const ALL: Self = Self((1 << 36) - 1);
+    pub const NONE: Self = Self(0);
+
+    pub fn connects(self, a: usize, b: usize) -> bool {
+        self.0 & (1 << (a * 6 + b)) != 0
+    }
+
+    // Connects every pair of faces in the mask, they were touched by one open region
+    fn connect_faces(&mut self, faces: u8) {
+        for a in 0..6 {
+            for b in 0..6 {
+                if faces & (1 << a) != 0 && faces & (1 << b) != 0 {
+                    self.0 |= 1 << (a * 6 + b);
+                }
+            }
+        }
+    }
+
+    // Flood fills each region of transparent voxels and connects the faces it reaches
+    pub fn compute(chunk: &Chunk, registry: &BlockRegistry) -> Self {
+        let opaque = |block_type: BlockType| !registry.is_transparent(block_type);
+        if chunk.is_uniform() {
+            return match chunk.get_voxel(0, 0, 0) {
+                Some(block_type) if opaque(block_type) => Self::NONE,
+                _ => Self::ALL,
+            };
+        }
+
+        let open: Vec<bool> = (0..CHUNK_VOLUME)
+            .map(|index| match chunk.get_voxel_by_index(index) {
+                Some(block_type) => !opaque(block_type),
+                None => false,
+            })
+            .collect();
+        let mut visited = vec![false; CHUNK_VOLUME];
+        let mut stack = Vec::new();
+        let mut connectivity = Self::NONE;
+        for start in 0..CHUNK_VOLUME {
+            if visited[start] || !open[start] {
+                continue;
+            }
+            visited[start] = true;
+            stack.push(start);
+            let mut faces = 0u8;
+            while let Some(index) = stack.pop() {
+                let local_pos = coords::index_to_local(index).unwrap();
+                for (face, direction) in FACES.iter().enumerate() {
+                    let neighbor = match coords::local_to_index(local_pos + *direction) {
+                        Some(neighbor) => neighbor,
+                        None => {
+                            faces |= 1 << face;
+                            continue;
+                        }
+                    };
+                    if !visited[neighbor] && open[neighbor] {
+                        visited[neighbor] = true;
+                        stack.push(neighbor);
+                    }
+                }
+            }
+            connectivity.connect_faces(faces);
+        }
+        connectivity
+    }
+}
+
+impl Default for ChunkConnectivity {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+// Chunks visible from the camera's chunk. The walk only moves through chunk_connectivity's
+// chunks, None is a chunk that isn't loaded and stops it. It never turns back towards the
+// camera, so sight lines can't bend around a wall and back out of a cave.
+pub fn visible_chunks(
+    camera_chunk: IVec3,
+    chunk_connectivity: impl Fn(IVec3) -> Option<ChunkConnectivity>,
+) -> HashSet<IVec3> {
+    let mut visible = HashSet::from([camera_chunk]);
+    // Chunk, face it was entered through, directions walked to get there
+    let mut queue = VecDeque::from([(camera_chunk, None::<usize>, 0u8)]);
+    while let Some((chunk_pos, entered, directions)) = queue.pop_front() {
+        let connectivity = chunk_connectivity(chunk_pos).unwrap_or_default();
+        for (face, direction) in FACES.iter().enumerate() {
+            if directions & (1 << opposite(face)) != 0 {
+                continue;
+            }
+            if let Some(entered) = entered {
+                if !connectivity.connects(entered, face) {
+                    continue;
+                }
+            }
+            let next = chunk_pos + *direction;
+            if visible.contains(&next) || chunk_connectivity(next).is_none() {
+                continue;
+            }
+            visible.insert(next);
+            queue.push_back((next, Some(opposite(face)), directions | (1 << face)));
+        }
+    }
+    visible
+}
+
+// Chunks with a mesh that were drawn and hidden last frame, for the overlay
+#[derive(Resource, Debug, Default)]
+pub struct CullingStats {
+    pub drawn: usize,
+    pub culled: usize,
+}
+
+// Hides the meshes of chunks the camera can't see into
+pub fn cull_hidden_chunks(
+    chunk_map: Res<ChunkMap>,
+    chunk_entities: Res<ChunkEntities>,
+    camera_query: Query<&FlyCamera>,
+    mut mesh_query: Query<(&ChunkMesh, &mut Visibility)>,
+    mut stats: ResMut<CullingStats>,
+) {
+    let camera = match camera_query.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let camera_chunk = coords::point_to_chunk(camera.get_position());
+    // Chunks still waiting for their first mesh are treated as open
+    let visible = visible_chunks(camera_chunk, |chunk_pos| {
+        if !chunk_map.map.contains_key(&chunk_pos) {
+            return None;
+        }
+        Some(
+            chunk_entities
+                .connectivity
+                .get(&chunk_pos)
+                .copied()
+                .unwrap_or_default(),
+        )
+    });
+
+    for (chunk_mesh, mut visibility) in mesh_query.iter_mut() {
+        let wanted = if visible.contains(&chunk_mesh.chunk_pos) {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        if *visibility != wanted {
+            *visibility = wanted;
+        }
+    }
+
+    let meshed: HashSet<IVec3> = chunk_entities
+        .map
+        .keys()
+        .chain(chunk_entities.translucent.keys())
+        .copied()
+        .collect();
+    let drawn = meshed
+        .iter()
+        .filter(|chunk_pos| visible.contains(chunk_pos))
+        .count();
+    stats.drawn = drawn;
+    stats.culled = meshed.len() - drawn;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn chunk_with(registry: &BlockRegistry, fill: impl Fn(IVec3) -> &'static str) -> Chunk {
+        let blocks: Vec<BlockType> = coords::local_positions()
+            .map(|local_pos| registry.id_or_air(fill(local_pos)))
+            .collect();
+        Chunk::from_blocks(&blocks)
+    }
+
+    #[test]
+    fn walls_split_chunk_faces() {
+        let registry = BlockRegistry::default();
+        let (x, neg_x, y, neg_y, z) = (0, 1, 2, 3, 4);
+
+        // A stone floor halfway up: the top and bottom don't see each other, the sides do
+        let floor = chunk_with(
+            &registry,
+            |local_pos| {
+                if local_pos.y == 16 {
+                    "stone"
+                } else {
+                    "air"
+                }
+            },
+        );
+        let connectivity = ChunkConnectivity::compute(&floor, &registry);
+        assert!(!connectivity.connects(y, neg_y));
+        assert!(connectivity.connects(x, neg_x));
+        assert!(connectivity.connects(x, y));
+        assert!(connectivity.connects(x, neg_y));
+
+        // A tunnel along x through solid stone
+        let tunnel = chunk_with(&registry, |local_pos| {
+            if local_pos.y == 10 && local_pos.z == 10 {
+                "air"
+            } else {
+                "stone"
+            }
+        });
+        let connectivity = ChunkConnectivity::compute(&tunnel, &registry);
+        assert!(connectivity.connects(x, neg_x));
+        assert!(!connectivity.connects(x, y));
+        assert!(!connectivity.connects(y, z));
+
+        assert_eq!(
+            ChunkConnectivity::compute(&Chunk::uniform(BlockType::AIR), &registry),
+            ChunkConnectivity::ALL
+        );
+    }
+
+    #[test]
+    fn chunks_behind_solid_ground_are_hidden() {
+        // The camera sits above a layer of solid chunks with caves below it
+        let mut chunks = HashMap::new();
+        for x in -2..=2 {
+            for y in -3..=1 {
+                for z in -2..=2 {
+                    let connectivity = if y == -1 {
+                        ChunkConnectivity::NONE
+                    } else {
+                        ChunkConnectivity::ALL
+                    };
+                    chunks.insert(IVec3::new(x, y, z), connectivity);
+                }
+            }
+        }
+        let visible = visible_chunks(IVec3::ZERO, |chunk_pos| chunks.get(&chunk_pos).copied());
+
+        // Everything above ground and the ground's surface can be seen
+        assert!(visible.contains(&IVec3::new(2, 1, -2)));
+        assert!(visible.contains(&IVec3::new(1, -1, 1)));
+        // Nothing gets through the ground to the caves
+        assert!(!visible.contains(&IVec3::new(0, -2, 0)));
+        assert!(!visible.contains(&IVec3::new(2, -3, 2)));
+        // Chunks that aren't loaded are never visible
+        assert!(!visible.contains(&IVec3::new(3, 0, 0)));
+    }
+}
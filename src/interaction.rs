@@ -0,0 +1,119 @@
+#![allow(dead_code)]
+use crate::{
+    block::{BlockType, ChunkMap},
+    camera::FlyCamera,
+    player::{Aabb, EYE_HEIGHT},
+    raycast::{self, RaycastHit},
+    registry::BlockRegistry,
+};
+use bevy::prelude::*;
+
+// How far away blocks can be picked, in blocks
+pub const REACH_DISTANCE: f32 = 8.0;
+
+// Block the camera is currently looking at
+#[derive(Resource, Default)]
+pub struct TargetBlock {
+    pub hit: Option<RaycastHit>,
+}
+
+// Block placed with the right mouse button
+#[derive(Resource)]
+pub struct SelectedBlock(pub BlockType);
+
+impl FromWorld for SelectedBlock {
+    fn from_world(world: &mut World) -> Self {
+        let registry = world.resource::<BlockRegistry>();
+        SelectedBlock(
+            registry
+                .id("stone")
+                .or_else(|| registry.placeable_blocks().next())
+                .unwrap_or(BlockType::AIR),
+        )
+    }
+}
+
+pub fn update_target_block(
+    camera_query: Query<&FlyCamera>,
+    chunk_map: Res<ChunkMap>,
+    registry: Res<BlockRegistry>,
+    mut target: ResMut<TargetBlock>,
+) {
+    target.hit = camera_query.get_single().ok().and_then(|camera| {
+        raycast::raycast(
+            &chunk_map,
+            &registry,
+            camera.get_position(),
+            camera.get_front(),
+            REACH_DISTANCE,
+        )
+    });
+}
+
+// Number keys pick from the registry's blocks in definition order
+pub fn select_block(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    registry: Res<BlockRegistry>,
+    mut selected: ResMut<SelectedBlock>,
+) {
+    let keys = [
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+        KeyCode::Digit0,
+    ];
+    for (key, block_type) in keys.into_iter().zip(registry.placeable_blocks()) {
+        if keyboard_input.just_pressed(key) {
+            selected.0 = block_type;
+            println!("Selected block: {}", registry.name(block_type));
+        }
+    }
+}
+
+// Left click breaks the targeted block, right click places the selected block against the
+// targeted face. While walking, solid blocks aren't placed where they would trap the player.
+pub fn edit_target_block(
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    camera_query: Query<&FlyCamera>,
+    target: Res<TargetBlock>,
+    selected: Res<SelectedBlock>,
+    registry: Res<BlockRegistry>,
+    mut chunk_map: ResMut<ChunkMap>,
+) {
+    let hit = match target.hit {
+        Some(hit) => hit,
+        None => return,
+    };
+
+    if mouse_input.just_pressed(MouseButton::Left) {
+        chunk_map.set_block(hit.block_pos, BlockType::AIR);
+    } else if mouse_input.just_pressed(MouseButton::Right) && hit.normal != IVec3::ZERO {
+        let place_pos = hit.block_pos + hit.normal;
+        let replaceable = chunk_map
+            .get_block(place_pos)
+            .is_some_and(|block_type| !registry.is_solid(block_type));
+        let inside_player = registry.is_solid(selected.0)
+            && camera_query.iter().any(|camera| {
+                let feet = camera.get_position() - Vec3::Y * EYE_HEIGHT;
+                !camera.is_flying() && Aabb::body(feet).intersects(Aabb::voxel(place_pos))
+            });
+        if replaceable && !inside_player {
+            chunk_map.set_block(place_pos, selected.0);
+        }
+    }
+}
+
+pub fn draw_target_outline(target: Res<TargetBlock>, mut gizmos: Gizmos) {
+    if let Some(hit) = target.hit {
+        // Slightly larger than the block so the outline isn't hidden inside its faces
+        let transform = Transform::from_translation(hit.block_pos.as_vec3() + Vec3::splat(0.5))
+            .with_scale(Vec3::splat(1.005));
+        gizmos.cuboid(transform, Color::BLACK);
+    }
+}
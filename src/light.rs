@@ -0,0 +1,698 @@
+#![allow(dead_code)]
+use crate::{
+    block::{BlockType, Chunk, ChunkMap, CHUNK_HEIGHT, CHUNK_VOLUME},
+    coords,
+    registry::BlockRegistry,
+};
+use bevy::prelude::*;
+use std::{collections::VecDeque, sync::Arc};
+
+// Light levels run from 0, pitch black, to 15, open sky or right next to a lamp
+pub const MAX_LIGHT: u8 = 15;
+// Translucent blocks like water take this much more light per block than air
+const TRANSLUCENT_FALLOFF: u8 = 1;
+
+// Newly loaded chunks joined into the light around them per frame, the rest wait for the next
+// frames so a burst of streamed in chunks doesn't stall one frame
+const CHUNKS_CONNECTED_PER_FRAME: usize = 4;
+
+const DIRECTIONS: [IVec3; 6] = [
+    IVec3::X,
+    IVec3::NEG_X,
+    IVec3::Y,
+    IVec3::NEG_Y,
+    IVec3::Z,
+    IVec3::NEG_Z,
+];
+
+// Every voxel carries two light levels: sky light falling in from above and block light from
+// emissive blocks. They spread the same way but are kept apart so removing one source doesn't
+// disturb the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightChannel {
+    Sky,
+    Block,
+}
+
+impl LightChannel {
+    pub const ALL: [LightChannel; 2] = [LightChannel::Sky, LightChannel::Block];
+
+    // Level of this channel in a packed light value
+    pub fn level(self, light: u8) -> u8 {
+        match self {
+            LightChannel::Sky => light >> 4,
+            LightChannel::Block => light & 0x0F,
+        }
+    }
+
+    pub fn with_level(self, light: u8, level: u8) -> u8 {
+        let level = level.min(MAX_LIGHT);
+        match self {
+            LightChannel::Sky => (light & 0x0F) | (level << 4),
+            LightChannel::Block => (light & 0xF0) | level,
+        }
+    }
+}
+
+// Both levels of a voxel in one byte, sky light in the high nibble
+pub fn pack_light(sky: u8, block: u8) -> u8 {
+    (sky.min(MAX_LIGHT) << 4) | block.min(MAX_LIGHT)
+}
+
+// Packed light of every voxel in a chunk, in storage order. Chunks up in the air or deep in
+// the rock are lit the same everywhere and keep a single value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LightStorage {
+    Uniform(u8),
+    Values(Box<[u8]>),
+}
+
+impl LightStorage {
+    pub fn from_values(values: Vec<u8>) -> Self {
+        match values.first() {
+            Some(first) if values.iter().all(|light| light == first) => {
+                LightStorage::Uniform(*first)
+            }
+            _ => LightStorage::Values(values.into_boxed_slice()),
+        }
+    }
+
+    pub fn get(&self, index: usize) -> u8 {
+        match self {
+            LightStorage::Uniform(light) => *light,
+            LightStorage::Values(values) => values.get(index).copied().unwrap_or(0),
+        }
+    }
+
+    pub fn set(&mut self, index: usize, light: u8) {
+        if let LightStorage::Uniform(current) = *self {
+            if current == light || index >= CHUNK_VOLUME {
+                return;
+            }
+            *self = LightStorage::Values(vec![current; CHUNK_VOLUME].into_boxed_slice());
+        }
+        if let LightStorage::Values(values) = self {
+            if let Some(value) = values.get_mut(index) {
+                *value = light;
+            }
+        }
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        match self {
+            LightStorage::Uniform(_) => 0,
+            LightStorage::Values(values) => values.len(),
+        }
+    }
+}
+
+// Levels lost stepping into a block, None if the block stops light altogether
+pub fn falloff(registry: &BlockRegistry, block_type: BlockType) -> Option<u8> {
+    if !registry.is_transparent(block_type) {
+        None
+    } else if registry.is_translucent(block_type) {
+        Some(1 + TRANSLUCENT_FALLOFF)
+    } else {
+        Some(1)
+    }
+}
+
+// Level a voxel lit at level passes to its neighbor in direction. Full sunlight falls straight
+// down through clear blocks without fading, that's what keeps open ground at full brightness.
+fn spread(channel: LightChannel, level: u8, direction: IVec3, falloff: u8) -> u8 {
+    if channel == LightChannel::Sky
+        && direction == IVec3::NEG_Y
+        && level == MAX_LIGHT
+        && falloff == 1
+    {
+        MAX_LIGHT
+    } else {
+        level.saturating_sub(falloff)
+    }
+}
+
+// How bright a light level looks. Each level is a bit darker than the last, but even level 0
+// isn't fully black so caves stay readable.
+pub fn brightness(level: u8) -> f32 {
+    let steps = MAX_LIGHT.saturating_sub(level) as i32;
+    0.04 + 0.96 * 0.8_f32.powi(steps)
+}
+
+// Color multiplier for a packed light value. Block light is a little warmer than daylight.
+pub fn light_tint(light: u8) -> [f32; 3] {
+    let sky = brightness(LightChannel::Sky.level(light));
+    let block = brightness(LightChannel::Block.level(light));
+    [sky.max(block), sky.max(block * 0.9), sky.max(block * 0.75)]
+}
+
+// Lights a chunk on its own, as if it was dark all around except for the sky light coming in
+// through the top. sky_above holds the sky level just above each column, in heightmap order.
+// Cheap enough for the worker threads; light from the neighbors is spread in by
+// update_light once the chunk is in the ChunkMap.
+pub fn light_chunk(chunk: &Chunk, registry: &BlockRegistry, sky_above: &[u8]) -> LightStorage {
+    if chunk.is_uniform() {
+        let block_type = chunk.get_voxel(0, 0, 0).unwrap_or(BlockType::AIR);
+        if registry.light_emission(block_type) == 0 {
+            match falloff(registry, block_type) {
+                None => return LightStorage::Uniform(0),
+                Some(1) if sky_above.iter().all(|level| *level == MAX_LIGHT) => {
+                    return LightStorage::Uniform(pack_light(MAX_LIGHT, 0))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let blocks: Vec<BlockType> = (0..CHUNK_VOLUME)
+        .map(|index| chunk.get_voxel_by_index(index).unwrap_or(BlockType::AIR))
+        .collect();
+    let mut values = vec![0; CHUNK_VOLUME];
+    let mut queue = VecDeque::new();
+
+    // Sunlight falls down each column until something stops it, then spreads sideways
+    for (column, level_above) in sky_above.iter().enumerate() {
+        let (x, z) = match coords::index_to_column(column) {
+            Some(column) => column,
+            None => continue,
+        };
+        let mut level = *level_above;
+        for y in (0..CHUNK_HEIGHT).rev() {
+            let index = coords::local_to_index(IVec3::new(x, y, z)).unwrap();
+            level = match falloff(registry, blocks[index]) {
+                Some(falloff) => spread(LightChannel::Sky, level, IVec3::NEG_Y, falloff),
+                None => 0,
+            };
+            if level == 0 {
+                break;
+            }
+            values[index] = LightChannel::Sky.with_level(values[index], level);
+            queue.push_back(index);
+        }
+    }
+    spread_in_chunk(
+        LightChannel::Sky,
+        registry,
+        &blocks,
+        &mut values,
+        &mut queue,
+    );
+
+    for (index, block_type) in blocks.iter().enumerate() {
+        let emission = registry.light_emission(*block_type);
+        if emission > 0 {
+            values[index] = LightChannel::Block.with_level(values[index], emission);
+            queue.push_back(index);
+        }
+    }
+    spread_in_chunk(
+        LightChannel::Block,
+        registry,
+        &blocks,
+        &mut values,
+        &mut queue,
+    );
+
+    LightStorage::from_values(values)
+}
+
+fn spread_in_chunk(
+    channel: LightChannel,
+    registry: &BlockRegistry,
+    blocks: &[BlockType],
+    values: &mut [u8],
+    queue: &mut VecDeque<usize>,
+) {
+    while let Some(index) = queue.pop_front() {
+        let level = channel.level(values[index]);
+        let local_pos = coords::index_to_local(index).unwrap();
+        for direction in DIRECTIONS {
+            let neighbor = match coords::local_to_index(local_pos + direction) {
+                Some(neighbor) => neighbor,
+                None => continue,
+            };
+            let falloff = match falloff(registry, blocks[neighbor]) {
+                Some(falloff) => falloff,
+                None => continue,
+            };
+            let new_level = spread(channel, level, direction, falloff);
+            if new_level > channel.level(values[neighbor]) {
+                values[neighbor] = channel.with_level(values[neighbor], new_level);
+                queue.push_back(neighbor);
+            }
+        }
+    }
+}
+
+// Spreads light outward from the queued voxels across every loaded chunk, only ever raising levels
+fn spread_light(
+    chunk_map: &mut ChunkMap,
+    registry: &BlockRegistry,
+    channel: LightChannel,
+    queue: &mut VecDeque<IVec3>,
+) {
+    while let Some(world_pos) = queue.pop_front() {
+        let level = match chunk_map.get_light(world_pos) {
+            Some(light) => channel.level(light),
+            None => continue,
+        };
+        if level <= 1 {
+            continue;
+        }
+        for direction in DIRECTIONS {
+            let neighbor = world_pos + direction;
+            let (block_type, light) =
+                match (chunk_map.get_block(neighbor), chunk_map.get_light(neighbor)) {
+                    (Some(block_type), Some(light)) => (block_type, light),
+                    _ => continue,
+                };
+            let falloff = match falloff(registry, block_type) {
+                Some(falloff) => falloff,
+                None => continue,
+            };
+            let new_level = spread(channel, level, direction, falloff);
+            if new_level > channel.level(light) {
+                chunk_map.set_light(neighbor, channel.with_level(light, new_level));
+                queue.push_back(neighbor);
+            }
+        }
+    }
+}
+
+// Takes back the light that spread out from the queued voxels, which are already cleared and
+// queued with the level they had. Neighbors lit from somewhere else keep their light and go
+// into refill, to spread it back into the cleared area afterwards.
+fn remove_light(
+    chunk_map: &mut ChunkMap,
+    registry: &BlockRegistry,
+    channel: LightChannel,
+    queue: &mut VecDeque<(IVec3, u8)>,
+    refill: &mut VecDeque<IVec3>,
+) {
+    while let Some((world_pos, level)) = queue.pop_front() {
+        for direction in DIRECTIONS {
+            let neighbor = world_pos + direction;
+            let (block_type, light) =
+                match (chunk_map.get_block(neighbor), chunk_map.get_light(neighbor)) {
+                    (Some(block_type), Some(light)) => (block_type, light),
+                    _ => continue,
+                };
+            let neighbor_level = channel.level(light);
+            if neighbor_level == 0 {
+                continue;
+            }
+            let lit_from_here = neighbor_level < level
+                || (channel == LightChannel::Sky
+                    && direction == IVec3::NEG_Y
+                    && level == MAX_LIGHT
+                    && neighbor_level == MAX_LIGHT);
+            if !lit_from_here || falloff(registry, block_type).is_none() {
+                refill.push_back(neighbor);
+                continue;
+            }
+            // Emissive blocks lose the light they got from around but keep their own
+            let own_level = match channel {
+                LightChannel::Sky => 0,
+                LightChannel::Block => registry.light_emission(block_type),
+            };
+            chunk_map.set_light(neighbor, channel.with_level(light, own_level));
+            queue.push_back((neighbor, neighbor_level));
+            if own_level > 0 {
+                refill.push_back(neighbor);
+            }
+        }
+    }
+}
+
+// Relights around a block that changed: clears the light that passed through it, then lets
+// the surroundings and the new block's own light spread back in
+pub fn update_block(chunk_map: &mut ChunkMap, registry: &BlockRegistry, world_pos: IVec3) {
+    let block_type = match chunk_map.get_block(world_pos) {
+        Some(block_type) => block_type,
+        None => return,
+    };
+    for channel in LightChannel::ALL {
+        let light = match chunk_map.get_light(world_pos) {
+            Some(light) => light,
+            None => return,
+        };
+        let mut removal = VecDeque::new();
+        let mut refill = VecDeque::new();
+        let level = channel.level(light);
+        if level > 0 {
+            chunk_map.set_light(world_pos, channel.with_level(light, 0));
+            removal.push_back((world_pos, level));
+        }
+        remove_light(chunk_map, registry, channel, &mut removal, &mut refill);
+
+        if channel == LightChannel::Block {
+            let emission = registry.light_emission(block_type);
+            if emission > 0 {
+                let light = chunk_map.get_light(world_pos).unwrap_or(0);
+                chunk_map.set_light(world_pos, channel.with_level(light, emission));
+                refill.push_back(world_pos);
+            }
+        }
+        if falloff(registry, block_type).is_some() {
+            refill.extend(DIRECTIONS.map(|direction| world_pos + direction));
+        }
+        spread_light(chunk_map, registry, channel, &mut refill);
+    }
+}
+
+// Sky light at the top of a column is full only if it fell straight in from the chunk above.
+// Clears the tops of lower_pos's columns that were lit assuming open sky but sit under a
+// loaded chunk that blocks it, e.g. a tree the heightmap didn't know about.
+fn queue_shaded_columns(
+    chunk_map: &mut ChunkMap,
+    registry: &BlockRegistry,
+    lower_pos: IVec3,
+    removal: &mut VecDeque<(IVec3, u8)>,
+) {
+    for column in 0..coords::CHUNK_COLUMNS {
+        let (x, z) = coords::index_to_column(column).unwrap();
+        let top = coords::local_to_world(lower_pos, IVec3::new(x, CHUNK_HEIGHT - 1, z));
+        let above = top + IVec3::Y;
+        let (light, above_light, top_block) = match (
+            chunk_map.get_light(top),
+            chunk_map.get_light(above),
+            chunk_map.get_block(top),
+        ) {
+            (Some(light), Some(above_light), Some(top_block)) => (light, above_light, top_block),
+            _ => continue,
+        };
+        if LightChannel::Sky.level(light) != MAX_LIGHT {
+            continue;
+        }
+        let falling = match falloff(registry, top_block) {
+            Some(falloff) => spread(
+                LightChannel::Sky,
+                LightChannel::Sky.level(above_light),
+                IVec3::NEG_Y,
+                falloff,
+            ),
+            None => 0,
+        };
+        if falling != MAX_LIGHT {
+            chunk_map.set_light(top, LightChannel::Sky.with_level(light, 0));
+            removal.push_back((top, MAX_LIGHT));
+        }
+    }
+}
+
+// Joins a newly loaded chunk into the light of the world around it. Chunks that come without
+// light are lit first, taking the sky from the chunk above or open sky if there is none.
+fn connect_chunk(chunk_map: &mut ChunkMap, registry: &BlockRegistry, chunk_pos: IVec3) {
+    let unlit = match chunk_map.map.get(&chunk_pos) {
+        Some(chunk) => chunk.light().is_none(),
+        None => return,
+    };
+    if unlit {
+        let sky_above: Vec<u8> = (0..coords::CHUNK_COLUMNS)
+            .map(|column| {
+                let (x, z) = coords::index_to_column(column).unwrap();
+                let above = coords::local_to_world(chunk_pos, IVec3::new(x, CHUNK_HEIGHT, z));
+                chunk_map
+                    .get_light(above)
+                    .map_or(MAX_LIGHT, |light| LightChannel::Sky.level(light))
+            })
+            .collect();
+        let chunk = chunk_map.map.get_mut(&chunk_pos).unwrap();
+        let light = light_chunk(chunk, registry, &sky_above);
+        Arc::make_mut(chunk).set_light(light);
+    }
+
+    let mut removal = VecDeque::new();
+    let mut refill = VecDeque::new();
+    queue_shaded_columns(chunk_map, registry, chunk_pos, &mut removal);
+    queue_shaded_columns(chunk_map, registry, chunk_pos - IVec3::Y, &mut removal);
+    remove_light(
+        chunk_map,
+        registry,
+        LightChannel::Sky,
+        &mut removal,
+        &mut refill,
+    );
+
+    // Light crosses every face the chunk shares with a loaded neighbor, in both directions
+    let mut border = Vec::new();
+    for local_pos in coords::local_positions() {
+        let on_border =
+            local_pos.cmpeq(IVec3::ZERO).any() || local_pos.cmpeq(coords::CHUNK_EXTENT - 1).any();
+        if !on_border {
+            continue;
+        }
+        let world_pos = coords::local_to_world(chunk_pos, local_pos);
+        border.push(world_pos);
+        for direction in DIRECTIONS {
+            if !coords::is_local(local_pos + direction) {
+                border.push(world_pos + direction);
+            }
+        }
+    }
+    for channel in LightChannel::ALL {
+        let mut queue: VecDeque<IVec3> = border.iter().copied().collect();
+        if channel == LightChannel::Sky {
+            queue.append(&mut refill);
+        }
+        spread_light(chunk_map, registry, channel, &mut queue);
+    }
+}
+
+// Brings the light up to date with the chunks loaded and blocks changed since the last call
+pub fn update_light(chunk_map: &mut ChunkMap, registry: &BlockRegistry) {
+    update_light_limited(chunk_map, registry, usize::MAX);
+}
+
+// update_light connecting at most max_chunks of the waiting chunks, oldest first. Block
+// changes are always caught up on, there are only ever a few of them.
+pub fn update_light_limited(chunk_map: &mut ChunkMap, registry: &BlockRegistry, max_chunks: usize) {
+    let count = max_chunks.min(chunk_map.unlit_chunks.len());
+    let chunks: Vec<IVec3> = chunk_map.unlit_chunks.drain(..count).collect();
+    for chunk_pos in chunks {
+        connect_chunk(chunk_map, registry, chunk_pos);
+    }
+    let edits = std::mem::take(&mut chunk_map.light_edits);
+    for world_pos in edits {
+        update_block(chunk_map, registry, world_pos);
+    }
+}
+
+// Runs before meshing so the meshes pick up the new light
+pub fn propagate_light(mut chunk_map: ResMut<ChunkMap>, registry: Res<BlockRegistry>) {
+    if chunk_map.unlit_chunks.is_empty() && chunk_map.light_edits.is_empty() {
+        return;
+    }
+    update_light_limited(&mut chunk_map, &registry, CHUNKS_CONNECTED_PER_FRAME);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{settings::TerrainSettings, terrain::TerrainGenerator};
+
+    fn sky(chunk_map: &ChunkMap, world_pos: IVec3) -> u8 {
+        LightChannel::Sky.level(chunk_map.get_light(world_pos).unwrap())
+    }
+
+    fn block(chunk_map: &ChunkMap, world_pos: IVec3) -> u8 {
+        LightChannel::Block.level(chunk_map.get_light(world_pos).unwrap())
+    }
+
+    // A chunk of air on top of a chunk filled with stone up to y = 10
+    fn ground(registry: &BlockRegistry) -> ChunkMap {
+        let stone = registry.id("stone").unwrap();
+        let mut chunk_map = ChunkMap::with_seed(0);
+        let voxels: Vec<BlockType> = coords::local_positions()
+            .map(|local_pos| {
+                if local_pos.y <= 10 {
+                    stone
+                } else {
+                    BlockType::AIR
+                }
+            })
+            .collect();
+        chunk_map.insert_chunk(IVec3::ZERO, Chunk::from_blocks(&voxels));
+        chunk_map.insert_chunk(IVec3::Y, Chunk::uniform(BlockType::AIR));
+        update_light(&mut chunk_map, registry);
+        chunk_map
+    }
+
+    #[test]
+    fn sky_light_stops_at_the_ground() {
+        let registry = BlockRegistry::default();
+        let stone = registry.id("stone").unwrap();
+        let mut chunk_map = ground(&registry);
+        assert_eq!(sky(&chunk_map, IVec3::new(5, 40, 5)), MAX_LIGHT);
+        assert_eq!(sky(&chunk_map, IVec3::new(5, 11, 5)), MAX_LIGHT);
+        assert_eq!(sky(&chunk_map, IVec3::new(5, 10, 5)), 0);
+
+        // A roof shades the ground under it, less the further in from its edge
+        for x in 0..12 {
+            for z in 0..12 {
+                chunk_map.set_block(IVec3::new(x, 14, z), stone);
+            }
+        }
+        update_light(&mut chunk_map, &registry);
+        assert_eq!(sky(&chunk_map, IVec3::new(0, 11, 0)), MAX_LIGHT - 12);
+        assert_eq!(sky(&chunk_map, IVec3::new(5, 11, 5)), MAX_LIGHT - 7);
+        assert_eq!(sky(&chunk_map, IVec3::new(11, 11, 11)), MAX_LIGHT - 1);
+
+        // Taking it away again brings the sunlight back
+        for x in 0..12 {
+            for z in 0..12 {
+                chunk_map.set_block(IVec3::new(x, 14, z), BlockType::AIR);
+            }
+        }
+        update_light(&mut chunk_map, &registry);
+        assert_eq!(sky(&chunk_map, IVec3::new(0, 11, 0)), MAX_LIGHT);
+    }
+
+    #[test]
+    fn block_light_fades_with_distance() {
+        let registry = BlockRegistry::default();
+        let lamp = registry.id("lamp").unwrap();
+        let mut chunk_map = ground(&registry);
+        // A little room dug into the stone, with a lamp in the middle
+        for x in 4..=12 {
+            for z in 4..=12 {
+                for y in 3..=5 {
+                    chunk_map.set_block(IVec3::new(x, y, z), BlockType::AIR);
+                }
+            }
+        }
+        chunk_map.set_block(IVec3::new(8, 3, 8), lamp);
+        update_light(&mut chunk_map, &registry);
+        assert_eq!(block(&chunk_map, IVec3::new(8, 3, 8)), MAX_LIGHT);
+        assert_eq!(block(&chunk_map, IVec3::new(8, 4, 8)), MAX_LIGHT - 1);
+        assert_eq!(block(&chunk_map, IVec3::new(12, 5, 12)), MAX_LIGHT - 10);
+        // Stone walls stay dark and no sunlight gets in
+        assert_eq!(block(&chunk_map, IVec3::new(13, 3, 8)), 0);
+        assert_eq!(sky(&chunk_map, IVec3::new(8, 4, 8)), 0);
+
+        chunk_map.set_block(IVec3::new(8, 3, 8), BlockType::AIR);
+        update_light(&mut chunk_map, &registry);
+        assert_eq!(block(&chunk_map, IVec3::new(8, 4, 8)), 0);
+        assert_eq!(block(&chunk_map, IVec3::new(12, 5, 12)), 0);
+    }
+
+    #[test]
+    fn incremental_updates_match_lighting_from_scratch() {
+        let registry = BlockRegistry::default();
+        let settings = TerrainSettings::default();
+        let generator = TerrainGenerator::new(3, &registry, &settings);
+        let stone = registry.id("stone").unwrap();
+        let lamp = registry.id("lamp").unwrap();
+        let chunk_positions: Vec<IVec3> = (0..2)
+            .flat_map(|x| (0..3).flat_map(move |y| (0..2).map(move |z| IVec3::new(x, y, z))))
+            .collect();
+        // Chunks without their light, so both maps light them the same way
+        let unlit = |chunk: Chunk| Chunk::from_storage(chunk.storage().clone());
+
+        let mut edited = ChunkMap::with_seed(0);
+        for chunk_pos in &chunk_positions {
+            edited.insert_chunk(*chunk_pos, unlit(generator.generate_chunk(*chunk_pos)));
+        }
+        update_light(&mut edited, &registry);
+        let surface = |chunk_map: &ChunkMap, x: i32, z: i32| {
+            (0..96)
+                .rev()
+                .map(|y| IVec3::new(x, y, z))
+                .find(|world_pos| chunk_map.get_block(*world_pos) != Some(BlockType::AIR))
+                .unwrap()
+        };
+        // A shaft dug across a chunk border, a lamp at its bottom, a roof over the hills and a
+        // lamp placed and taken away again
+        let top = surface(&edited, 31, 20);
+        for y in top.y - 12..=top.y {
+            edited.set_block(IVec3::new(31, y, 20), BlockType::AIR);
+            edited.set_block(IVec3::new(32, y, 20), BlockType::AIR);
+        }
+        edited.set_block(IVec3::new(31, top.y - 12, 20), lamp);
+        update_light(&mut edited, &registry);
+        for x in 10..30 {
+            for z in 10..30 {
+                edited.set_block(IVec3::new(x, 90, z), stone);
+            }
+        }
+        let lamp_pos = surface(&edited, 40, 40) + IVec3::Y;
+        edited.set_block(lamp_pos, lamp);
+        update_light(&mut edited, &registry);
+        edited.set_block(lamp_pos, BlockType::AIR);
+        update_light(&mut edited, &registry);
+
+        let mut fresh = ChunkMap::with_seed(0);
+        for chunk_pos in &chunk_positions {
+            fresh.insert_chunk(
+                *chunk_pos,
+                unlit(edited.get_chunk(*chunk_pos).unwrap().clone()),
+            );
+        }
+        update_light(&mut fresh, &registry);
+
+        for chunk_pos in &chunk_positions {
+            for local_pos in coords::local_positions() {
+                let world_pos = coords::local_to_world(*chunk_pos, local_pos);
+                assert_eq!(
+                    edited.get_light(world_pos),
+                    fresh.get_light(world_pos),
+                    "light differs at {}",
+                    world_pos
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn chunks_connected_over_several_frames_match_all_at_once() {
+        let registry = BlockRegistry::default();
+        let generator = TerrainGenerator::new(3, &registry, &TerrainSettings::default());
+        let chunk_positions: Vec<IVec3> = (0..2)
+            .flat_map(|x| (0..3).flat_map(move |y| (0..2).map(move |z| IVec3::new(x, y, z))))
+            .collect();
+        let mut at_once = ChunkMap::with_seed(0);
+        for chunk_pos in &chunk_positions {
+            at_once.insert_chunk(*chunk_pos, generator.generate_chunk(*chunk_pos));
+        }
+        update_light(&mut at_once, &registry);
+
+        // Two chunks arrive every frame but only one gets connected, like while streaming, and
+        // in the opposite order
+        let mut spread_out = ChunkMap::with_seed(0);
+        let mut frames = 0;
+        for pair in chunk_positions.rchunks(2) {
+            for chunk_pos in pair {
+                spread_out.insert_chunk(*chunk_pos, generator.generate_chunk(*chunk_pos));
+            }
+            update_light_limited(&mut spread_out, &registry, 1);
+            frames += 1;
+        }
+        assert_eq!(
+            spread_out.unlit_chunks.len(),
+            chunk_positions.len() - frames
+        );
+        // A chunk unloaded and loaded again before its turn waits only once
+        let waiting = spread_out.unlit_chunks[0];
+        let chunk = spread_out.remove_chunk(waiting).unwrap();
+        assert!(!spread_out.unlit_chunks.contains(&waiting));
+        spread_out.insert_chunk(waiting, (*chunk).clone());
+        assert_eq!(
+            spread_out.unlit_chunks.len(),
+            chunk_positions.len() - frames
+        );
+        while !spread_out.unlit_chunks.is_empty() {
+            update_light_limited(&mut spread_out, &registry, 1);
+        }
+
+        for chunk_pos in &chunk_positions {
+            for local_pos in coords::local_positions() {
+                let world_pos = coords::local_to_world(*chunk_pos, local_pos);
+                assert_eq!(
+                    at_once.get_light(world_pos),
+                    spread_out.get_light(world_pos),
+                    "light differs at {}",
+                    world_pos
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,366 @@
+#![allow(dead_code)]
+use crate::{
+    block::{BlockType, Chunk, ChunkMap, ChunkNeighborhood, CHUNK_VOLUME},
+    camera::FlyCamera,
+    coords,
+    light::{LightChannel, LightStorage},
+    mesh::{self, AmbientOcclusion, ChunkMeshData, MeshingMode},
+    registry::BlockRegistry,
+};
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+// Where distant chunks switch to coarser meshes. Level n merges 2^n voxels along each axis
+// into one block, so level 3 turns a chunk into 4 x 4 x 4 blocks of 8 x 8 x 8.
+#[derive(Resource, Debug, Clone)]
+pub struct LodSettings {
+    // Distance in chunks from the camera's chunk where each level starts
+    pub rings: Vec<f32>,
+    // A chunk keeps its level until it is this far past a ring, so chunks near a ring don't
+    // switch back and forth while the camera moves around
+    pub hysteresis: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            rings: vec![3.0, 5.0, 7.0],
+            hysteresis: 0.5,
+        }
+    }
+}
+
+impl LodSettings {
+    // Level of a chunk at the given distance, given the level it has now if any
+    pub fn level(&self, current: Option<u8>, distance: f32) -> u8 {
+        let target = self.rings.iter().filter(|ring| distance >= **ring).count() as u8;
+        let current = match current {
+            Some(current) if current != target && current as usize <= self.rings.len() => current,
+            _ => return target,
+        };
+        let start = match current {
+            0 => f32::MIN,
+            level => self.rings[level as usize - 1],
+        };
+        let end = self
+            .rings
+            .get(current as usize)
+            .copied()
+            .unwrap_or(f32::MAX);
+        if distance >= start - self.hysteresis && distance < end + self.hysteresis {
+            current
+        } else {
+            target
+        }
+    }
+}
+
+// Voxels merged along each axis at a level
+pub fn lod_scale(level: u8) -> i32 {
+    1 << level.min(5)
+}
+
+// Level every loaded chunk is meshed at
+#[derive(Resource, Debug, Default)]
+pub struct ChunkLods {
+    pub levels: HashMap<IVec3, u8>,
+}
+
+impl ChunkLods {
+    pub fn level(&self, chunk_pos: IVec3) -> u8 {
+        self.levels.get(&chunk_pos).copied().unwrap_or(0)
+    }
+}
+
+fn chunk_distance(chunk_pos: IVec3, camera_chunk: IVec3) -> f32 {
+    (chunk_pos - camera_chunk).as_vec3().length()
+}
+
+// Merges every scale^3 cell of the chunk into a single block repeated over the whole cell, so
+// the result is still a full chunk the mesher can walk and the greedy mesher turns each cell
+// side into one quad. A cell is solid when at least half of it is, with the block most common
+// on its highest filled layer, so grass and snow still show on top from afar. Cells take the
+// brightest light inside them.
+pub fn downsample_chunk(chunk: &Chunk, scale: i32) -> Chunk {
+    if scale <= 1 || chunk.is_uniform() {
+        return chunk.clone();
+    }
+    let cells = coords::CHUNK_EXTENT / scale;
+    let cell_volume = (scale * scale * scale) as usize;
+    let mut voxels = vec![BlockType::AIR; CHUNK_VOLUME];
+    let mut light = chunk.light().map(|_| vec![0; CHUNK_VOLUME]);
+
+    for cell_x in 0..cells.x {
+        for cell_y in 0..cells.y {
+            for cell_z in 0..cells.z {
+                let origin = IVec3::new(cell_x, cell_y, cell_z) * scale;
+                let cell_positions = || {
+                    (0..scale).flat_map(move |x| {
+                        (0..scale).flat_map(move |y| {
+                            (0..scale).map(move |z| origin + IVec3::new(x, y, z))
+                        })
+                    })
+                };
+
+                let mut filled = 0;
+                let mut surface: Option<(i32, Vec<(BlockType, usize)>)> = None;
+                let mut sky = 0;
+                let mut block_light = 0;
+                for local_pos in cell_positions() {
+                    let block_type = chunk
+                        .get_voxel(local_pos.x, local_pos.y, local_pos.z)
+                        .unwrap_or(BlockType::AIR);
+                    if let Some(packed) = chunk.get_light(local_pos) {
+                        sky = sky.max(LightChannel::Sky.level(packed));
+                        block_light = block_light.max(LightChannel::Block.level(packed));
+                    }
+                    if block_type == BlockType::AIR {
+                        continue;
+                    }
+                    filled += 1;
+                    // Tally the blocks of the highest layer holding anything
+                    match &mut surface {
+                        Some((y, _)) if *y > local_pos.y => continue,
+                        Some((y, counts)) if *y == local_pos.y => {
+                            match counts
+                                .iter_mut()
+                                .find(|(counted, _)| *counted == block_type)
+                            {
+                                Some((_, count)) => *count += 1,
+                                None => counts.push((block_type, 1)),
+                            }
+                        }
+                        _ => surface = Some((local_pos.y, vec![(block_type, 1)])),
+                    }
+                }
+
+                let block_type = match surface {
+                    Some((_, counts)) if filled * 2 >= cell_volume => counts
+                        .iter()
+                        .max_by_key(|(_, count)| *count)
+                        .map(|(block_type, _)| *block_type)
+                        .unwrap_or(BlockType::AIR),
+                    _ => BlockType::AIR,
+                };
+                let packed = LightChannel::Block.with_level(sky << 4, block_light);
+                for local_pos in cell_positions() {
+                    let index = coords::local_to_index(local_pos).unwrap();
+                    voxels[index] = block_type;
+                    if let Some(light) = &mut light {
+                        light[index] = packed;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut downsampled = Chunk::from_blocks(&voxels);
+    if let Some(light) = light {
+        downsampled.set_light(LightStorage::from_values(light));
+    }
+    downsampled
+}
+
+// Neighborhood of a chunk meshed at level. Neighbors at the same level are merged the same way
+// so the faces between them line up. The others are left out, which makes the chunk close its
+// sides with walls along the border: skirts hiding the cracks where its coarse surface meets
+// the finer or coarser one next door.
+pub fn downsample_neighborhood(
+    neighborhood: &ChunkNeighborhood,
+    level: u8,
+    neighbor_level: impl Fn(IVec3) -> u8,
+) -> ChunkNeighborhood {
+    let scale = lod_scale(level);
+    neighborhood.map_chunks(|offset, chunk| {
+        (offset == IVec3::ZERO || neighbor_level(offset) == level)
+            .then(|| downsample_chunk(chunk, scale))
+    })
+}
+
+// Meshes a chunk at its level. Coarse chunks always use the greedy mesher, the naive one would
+// still give every merged voxel its own faces.
+pub fn generate_lod_mesh(
+    neighborhood: &ChunkNeighborhood,
+    registry: &BlockRegistry,
+    mode: MeshingMode,
+    ambient_occlusion: AmbientOcclusion,
+    level: u8,
+    neighbor_levels: &HashMap<IVec3, u8>,
+) -> ChunkMeshData {
+    if level == 0 {
+        return mesh::generate_mesh(neighborhood, registry, mode, ambient_occlusion);
+    }
+    let neighborhood = downsample_neighborhood(neighborhood, level, |offset| {
+        neighbor_levels.get(&offset).copied().unwrap_or(0)
+    });
+    mesh::generate_mesh(
+        &neighborhood,
+        registry,
+        MeshingMode::Greedy,
+        ambient_occlusion,
+    )
+}
+
+// Levels of the chunks around chunk_pos by their offset, for the meshing task
+pub fn neighbor_levels(lods: &ChunkLods, chunk_pos: IVec3) -> HashMap<IVec3, u8> {
+    let mut levels = HashMap::new();
+    for x in -1..=1 {
+        for y in -1..=1 {
+            for z in -1..=1 {
+                let offset = IVec3::new(x, y, z);
+                levels.insert(offset, lods.level(chunk_pos + offset));
+            }
+        }
+    }
+    levels
+}
+
+// Works out the level of every loaded chunk from its distance to the camera. A chunk that
+// changes level is remeshed along with its neighbors, whose skirts depend on it. The old mesh
+// stays up until the new one is ready, so nothing flickers out in between.
+pub fn update_chunk_lods(
+    mut chunk_map: ResMut<ChunkMap>,
+    mut lods: ResMut<ChunkLods>,
+    settings: Res<LodSettings>,
+    camera_query: Query<&FlyCamera>,
+) {
+    let camera = match camera_query.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let camera_chunk = coords::point_to_chunk(camera.get_position());
+
+    lods.levels
+        .retain(|chunk_pos, _| chunk_map.map.contains_key(chunk_pos));
+    let mut changed = Vec::new();
+    for chunk_pos in chunk_map.map.keys() {
+        let current = lods.levels.get(chunk_pos).copied();
+        let level = settings.level(current, chunk_distance(*chunk_pos, camera_chunk));
+        if current == Some(level) {
+            continue;
+        }
+        lods.levels.insert(*chunk_pos, level);
+        // New chunks are meshed anyway
+        if current.is_some() {
+            changed.push(*chunk_pos);
+        }
+    }
+    for chunk_pos in changed {
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    chunk_map.mark_dirty(chunk_pos + IVec3::new(x, y, z));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        block::CHUNK_SIZE, mesh::validate_mesh, settings::TerrainSettings,
+        terrain::TerrainGenerator,
+    };
+
+    #[test]
+    fn levels_stick_until_past_the_hysteresis() {
+        let settings = LodSettings::default();
+        assert_eq!(settings.level(None, 2.9), 0);
+        assert_eq!(settings.level(None, 3.1), 1);
+        assert_eq!(settings.level(None, 7.5), 3);
+        // Just past a ring nothing changes yet, in either direction
+        assert_eq!(settings.level(Some(0), 3.2), 0);
+        assert_eq!(settings.level(Some(1), 2.7), 1);
+        assert_eq!(settings.level(Some(0), 3.6), 1);
+        assert_eq!(settings.level(Some(1), 2.4), 0);
+        // Jumps over several rings go straight to the new level
+        assert_eq!(settings.level(Some(0), 10.0), 3);
+    }
+
+    // Stone up to y = 9 with grass on top
+    fn ground(registry: &BlockRegistry) -> Chunk {
+        let stone = registry.id("stone").unwrap();
+        let grass = registry.id("grass").unwrap();
+        let voxels: Vec<BlockType> = coords::local_positions()
+            .map(|local_pos| match local_pos.y {
+                0..=8 => stone,
+                9 => grass,
+                _ => BlockType::AIR,
+            })
+            .collect();
+        Chunk::from_blocks(&voxels)
+    }
+
+    #[test]
+    fn cells_keep_their_surface_block() {
+        let registry = BlockRegistry::default();
+        let chunk = downsample_chunk(&ground(&registry), 4);
+        let block_at = |y: i32| chunk.get_voxel(3, y, 5).unwrap();
+        assert_eq!(block_at(0), registry.id("stone").unwrap());
+        // The cell from 8 to 11 is half filled, its top layer is grass
+        assert_eq!(block_at(8), registry.id("grass").unwrap());
+        assert_eq!(block_at(11), registry.id("grass").unwrap());
+        assert_eq!(block_at(12), BlockType::AIR);
+        // A quarter filled cell is left empty
+        assert_eq!(
+            downsample_chunk(&ground(&registry), 8).get_voxel(0, 8, 0),
+            Some(BlockType::AIR)
+        );
+    }
+
+    #[test]
+    fn coarse_meshes_are_smaller_and_closed_by_skirts() {
+        let registry = BlockRegistry::default();
+        let generator = TerrainGenerator::new(5, &registry, &TerrainSettings::default());
+        let mut chunk_map = ChunkMap::new();
+        for chunk_pos in [IVec3::ZERO, IVec3::X, IVec3::Y] {
+            chunk_map.insert_chunk(chunk_pos, generator.generate_chunk(chunk_pos));
+        }
+        let neighborhood = chunk_map.neighborhood(IVec3::ZERO).unwrap();
+        let ao = AmbientOcclusion(true);
+        let mesh = |level: u8, levels: &HashMap<IVec3, u8>| {
+            generate_lod_mesh(
+                &neighborhood,
+                &registry,
+                MeshingMode::Greedy,
+                ao,
+                level,
+                levels,
+            )
+            .opaque
+        };
+        // Area of the faces on the border with the +x neighbor
+        let border_area = |mesh: &mesh::MeshData| -> f32 {
+            mesh.vertices
+                .chunks_exact(4)
+                .zip(mesh.normals.chunks_exact(4))
+                .filter(|(quad, normals)| {
+                    normals[0] == [1.0, 0.0, 0.0] && quad[0][0] == CHUNK_SIZE as f32
+                })
+                .map(|(quad, _)| {
+                    let min = quad
+                        .iter()
+                        .fold(Vec3::MAX, |min, v| min.min(Vec3::from_array(*v)));
+                    let max = quad
+                        .iter()
+                        .fold(Vec3::MIN, |max, v| max.max(Vec3::from_array(*v)));
+                    (max.y - min.y) * (max.z - min.z)
+                })
+                .sum()
+        };
+
+        let full = mesh(0, &HashMap::new());
+        let same_level: HashMap<IVec3, u8> = [(IVec3::X, 2), (IVec3::Y, 2)].into();
+        let coarse = mesh(2, &same_level);
+        assert_eq!(validate_mesh(&coarse), Ok(()));
+        assert!(coarse.vertices.len() < full.vertices.len());
+
+        // Next to a finer chunk the coarse one walls off its side
+        let finer_neighbor: HashMap<IVec3, u8> = [(IVec3::X, 1), (IVec3::Y, 2)].into();
+        let skirted = mesh(2, &finer_neighbor);
+        assert_eq!(validate_mesh(&skirted), Ok(()));
+        assert!(border_area(&skirted) > border_area(&coarse));
+    }
+}
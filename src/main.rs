@@ -3,31 +3,86 @@ use bevy::{
     diagnostic::FrameTimeDiagnosticsPlugin,
     pbr::wireframe::{WireframeConfig, WireframePlugin},
     prelude::*,
-    render::{
-        mesh::{Indices, PrimitiveTopology},
-        render_asset::RenderAssetUsages,
-    },
 };
-use mesh::MeshData;
 
+mod atlas;
+mod biome;
 mod block;
 mod camera;
+mod coords;
+mod culling;
+mod interaction;
+mod light;
+mod lod;
 mod mesh;
+mod ore;
+mod palette;
+mod player;
+mod raycast;
+mod registry;
+mod save;
+mod seed;
+mod settings;
+mod terrain;
 mod utils;
+mod world;
 
 fn main() {
+    let terrain_settings =
+        settings::TerrainSettings::load_or_default(settings::TERRAIN_SETTINGS_PATH);
+    // The chunks of an existing world were generated from its seed, it wins over the requested one
+    let world_save = save::WorldSave::default();
+    let saved_seed = world_save.load_seed().unwrap_or_else(|err| {
+        println!("Failed to load world {}: {}", world_save.dir.display(), err);
+        None
+    });
+    if saved_seed.is_some() && seed::seed_argument(std::env::args()).is_some() {
+        println!(
+            "Ignoring --seed, {} keeps its own seed. Move it away to start a new world.",
+            world_save.dir.display()
+        );
+    }
+    let (world_seed, seed_source) =
+        seed::world_seed(std::env::args(), &terrain_settings, saved_seed);
+    println!("Using seed {} from {}", world_seed, seed_source);
+
     App::new()
         .add_plugins(DefaultPlugins)
         .add_plugins(WireframePlugin)
-        .add_plugins(FrameTimeDiagnosticsPlugin::default())
+        .add_plugins(FrameTimeDiagnosticsPlugin)
+        .add_plugins(MaterialPlugin::<atlas::BlockMaterial>::default())
         .insert_resource(camera::FlyCamera::default())
         .add_systems(Startup, (setup, utils::setup_fps_counter))
-        .add_systems(Update, (utils::update_fps, utils::toggle_wireframe_system))
-        .insert_resource(block::ChunkMap::new())
+        .add_systems(
+            Update,
+            (
+                utils::update_fps,
+                utils::update_seed_text,
+                utils::update_culling_text,
+                utils::toggle_wireframe_system,
+            ),
+        )
+        .insert_resource(registry::BlockRegistry::load_or_default(
+            registry::BLOCKS_PATH,
+        ))
+        .insert_resource(terrain_settings)
+        .init_resource::<settings::TerrainSettingsWatcher>()
+        .insert_resource(block::ChunkMap::with_seed(world_seed))
+        .init_resource::<world::ChunkEntities>()
+        .init_resource::<world::ChunkMaterial>()
+        .init_resource::<mesh::MeshingMode>()
+        .init_resource::<mesh::AmbientOcclusion>()
+        .init_resource::<lod::LodSettings>()
+        .init_resource::<lod::ChunkLods>()
+        .init_resource::<culling::CullingStats>()
+        .init_resource::<world::ChunkStreaming>()
+        .init_resource::<world::ChunkTasks>()
+        .insert_resource(world_save)
+        .init_resource::<interaction::TargetBlock>()
+        .init_resource::<interaction::SelectedBlock>()
         .insert_resource(WireframeConfig {
             global: false,
             default_color: Color::WHITE,
-            ..Default::default()
         })
         .insert_resource(utils::WireframeState::default())
         .add_systems(
@@ -35,18 +90,56 @@ fn main() {
             (
                 camera::process_keyboard,
                 camera::process_mouse,
-                camera::update_camera,
+                camera::update_camera.after(player::move_player),
+                player::toggle_walking,
+                player::move_player
+                    .after(player::toggle_walking)
+                    .after(camera::process_mouse),
+                world::toggle_meshing_mode,
+                world::toggle_ambient_occlusion,
+                world::print_memory_stats,
+                save::save_world_system,
+                settings::reload_terrain_settings,
             ),
         )
+        .add_systems(
+            Update,
+            (
+                world::regenerate_terrain,
+                world::stream_chunks,
+                world::poll_generation_tasks,
+                light::propagate_light,
+                lod::update_chunk_lods,
+                world::queue_mesh_tasks,
+                world::poll_mesh_tasks,
+                culling::cull_hidden_chunks,
+            )
+                .chain()
+                .after(camera::process_keyboard)
+                .after(player::move_player)
+                .after(world::toggle_meshing_mode)
+                .after(world::toggle_ambient_occlusion)
+                .after(settings::reload_terrain_settings),
+        )
+        .add_systems(
+            Update,
+            (
+                interaction::select_block,
+                interaction::update_target_block,
+                interaction::edit_target_block,
+                interaction::draw_target_outline,
+            )
+                .chain()
+                .after(camera::process_keyboard)
+                .after(camera::process_mouse)
+                .after(player::move_player)
+                .before(light::propagate_light),
+        )
+        .add_systems(Last, save::save_on_exit)
         .run();
 }
 
-fn setup(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut chunk_map: ResMut<block::ChunkMap>,
-) {
+fn setup(mut commands: Commands) {
     // Spawn 3D camera
     commands.spawn((
         Camera3dBundle {
@@ -54,6 +147,7 @@ fn setup(
             ..Default::default()
         },
         camera::FlyCamera::default(),
+        player::Player::default(),
     ));
 
     // Add light source
@@ -64,41 +158,7 @@ fn setup(
             shadows_enabled: true,
             ..Default::default()
         },
-        transform: Transform::from_xyz(4.0, 64.0, 64.0),
-        ..Default::default()
-    });
-
-    // Generate terrain with heightmap
-    let world_size = IVec3::new(5, 1, 5);
-    chunk_map.generate_terrain(world_size);
-
-    // let (vertices, indices, normals, colors) = block::generate_mesh(&chunk_map);
-
-    let MeshData {
-        vertices,
-        indices,
-        normals,
-        colors,
-    } = mesh::generate_mesh(&chunk_map);
-
-    let mut meshs = Mesh::new(
-        PrimitiveTopology::TriangleList,
-        RenderAssetUsages::default(),
-    );
-    meshs.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
-    meshs.insert_indices(Indices::U32(indices));
-    meshs.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
-    meshs.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-    let mesh_handle = meshes.add(meshs);
-
-    commands.spawn(PbrBundle {
-        mesh: mesh_handle,
-        material: materials.add(StandardMaterial {
-            //base_color: Color::srgb(0.8, 0.0, 0.0),
-            alpha_mode: AlphaMode::AlphaToCoverage,
-            cull_mode: None,
-            ..Default::default()
-        }),
+        transform: Transform::from_xyz(4.0, 256.0, 64.0),
         ..Default::default()
     });
 }
@@ -1,7 +1,18 @@
 #![allow(dead_code)]
-use crate::block::{self, BlockType};
-use bevy::prelude::*;
-use std::collections::HashMap;
+use crate::{
+    atlas,
+    block::{BlockType, ChunkNeighborhood, CHUNK_HEIGHT, CHUNK_SIZE},
+    culling::ChunkConnectivity,
+    light::{self, LightChannel, MAX_LIGHT},
+    registry::BlockRegistry,
+};
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::{Indices, PrimitiveTopology},
+        render_asset::RenderAssetUsages,
+    },
+};
 
 #[derive(Debug)]
 pub struct MeshData {
@@ -9,6 +20,10 @@ pub struct MeshData {
     pub indices: Vec<u32>,
     pub normals: Vec<[f32; 3]>,
     pub colors: Vec<[f32; 4]>,
+    // Face position in blocks, the shader repeats the tile once per block
+    pub uvs: Vec<[f32; 2]>,
+    // Atlas origin of the face's tile
+    pub tile_uvs: Vec<[f32; 2]>,
 }
 
 impl MeshData {
@@ -18,6 +33,8 @@ impl MeshData {
             indices: Vec::new(),
             normals: Vec::new(),
             colors: Vec::new(),
+            uvs: Vec::new(),
+            tile_uvs: Vec::new(),
         }
     }
 
@@ -26,6 +43,8 @@ impl MeshData {
         self.indices.clear();
         self.normals.clear();
         self.colors.clear();
+        self.uvs.clear();
+        self.tile_uvs.clear();
     }
 
     pub fn insert_mesh(&mut self, mesh: &MeshData) {
@@ -33,260 +52,801 @@ impl MeshData {
         self.indices.extend(&mesh.indices);
         self.normals.extend(&mesh.normals);
         self.colors.extend(&mesh.colors);
+        self.uvs.extend(&mesh.uvs);
+        self.tile_uvs.extend(&mesh.tile_uvs);
+    }
+
+    // Adds texture coordinates for the last four vertices, which make up a face pointing along normal
+    fn push_face_uvs(&mut self, normal: IVec3, tile_origin: [f32; 2]) {
+        let start = self.vertices.len() - 4;
+        for index in start..self.vertices.len() {
+            self.uvs.push(face_uv(self.vertices[index], normal));
+        }
+        self.tile_uvs.extend([tile_origin; 4]);
+    }
+
+    pub fn into_mesh(self) -> Mesh {
+        let mut mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        );
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.vertices);
+        mesh.insert_indices(Indices::U32(self.indices));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_1, self.tile_uvs);
+        mesh
     }
 }
 
-pub fn generate_mesh(chunk_map: &block::ChunkMap) -> MeshData {
-    let mut mesh = MeshData::new();
-    let mut index_offset: u32 = 0;
-    let mut world_map: block::WorldMap = block::WorldMap::new();
-    world_map.collect_voxels(chunk_map);
-
-    let terrain_voxels: HashMap<(i32, i32, i32), block::Voxel> = world_map.map;
-    for ((x, y, z), voxel) in &terrain_voxels {
-        let voxel_pos = Vec3::new(*x as f32, *y as f32, *z as f32);
-
-        if voxel.is_solid {
-            // Add top face
-            if !terrain_voxels
-                .get(&(*x, y + 1, *z))
-                .map_or(false, |v| v.is_solid)
-                || terrain_voxels
-                    .get(&(*x, y + 1, *z))
-                    .map_or(false, |v| v.block_type == BlockType::Water)
-            {
-                add_top(&mut mesh, voxel_pos, &voxel.block_type, index_offset);
-                index_offset += 4;
-            }
-            // Add bottom face
-            if !terrain_voxels
-                .get(&(*x, y - 1, *z))
-                .map_or(false, |v| v.is_solid)
-                || terrain_voxels
-                    .get(&(*x, y - 1, *z))
-                    .map_or(false, |v| v.block_type == BlockType::Water)
-            {
-                add_bottom(&mut mesh, voxel_pos, &voxel.block_type, index_offset);
-                index_offset += 4;
-            }
-            // Add left face
-            if !terrain_voxels
-                .get(&(*x - 1, *y, *z))
-                .map_or(false, |v| v.is_solid)
-                || terrain_voxels
-                    .get(&(*x - 1, *y, *z))
-                    .map_or(false, |v| v.block_type == BlockType::Water)
-            {
-                add_left(&mut mesh, voxel_pos, &voxel.block_type, index_offset);
-                index_offset += 4;
-            }
-            // Add right face
-            if !terrain_voxels
-                .get(&(*x + 1, *y, *z))
-                .map_or(false, |v| v.is_solid)
-                || terrain_voxels
-                    .get(&(*x + 1, *y, *z))
-                    .map_or(false, |v| v.block_type == BlockType::Water)
-            {
-                add_right(&mut mesh, voxel_pos, &voxel.block_type, index_offset);
-                index_offset += 4;
+// Meshes of one chunk. Translucent blocks like water go into their own mesh so they can be
+// drawn blended after the opaque terrain.
+#[derive(Debug)]
+pub struct ChunkMeshData {
+    pub opaque: MeshData,
+    pub translucent: MeshData,
+    // Which faces of the chunk see each other, for cave culling
+    pub connectivity: ChunkConnectivity,
+}
+
+impl ChunkMeshData {
+    pub fn new() -> Self {
+        Self {
+            opaque: MeshData::new(),
+            translucent: MeshData::new(),
+            connectivity: ChunkConnectivity::ALL,
+        }
+    }
+
+    // Mesh the faces of block_type belong to
+    fn layer_mut(&mut self, registry: &BlockRegistry, block_type: BlockType) -> &mut MeshData {
+        if registry.is_translucent(block_type) {
+            &mut self.translucent
+        } else {
+            &mut self.opaque
+        }
+    }
+}
+
+// Texture coordinates of a face vertex in blocks. Side faces use -y for v so textures stand
+// upright, and u runs so that no face shows its texture mirrored.
+fn face_uv(position: [f32; 3], normal: IVec3) -> [f32; 2] {
+    let [x, y, z] = position;
+    match (normal.x, normal.y, normal.z) {
+        (1, _, _) => [-z, -y],
+        (-1, _, _) => [z, -y],
+        (_, _, 1) => [x, -y],
+        (_, _, -1) => [-x, -y],
+        _ => [x, z],
+    }
+}
+
+// Checks what the renderer relies on: one entry per vertex in every attribute, indices forming
+// whole triangles inside the vertex range, unit normals, and every triangle wound
+// counter-clockwise when seen from the side its normals point to, so back-face culling keeps it.
+pub fn validate_mesh(mesh: &MeshData) -> Result<(), String> {
+    let vertex_count = mesh.vertices.len();
+    for (name, len) in [
+        ("normals", mesh.normals.len()),
+        ("colors", mesh.colors.len()),
+        ("uvs", mesh.uvs.len()),
+        ("tile_uvs", mesh.tile_uvs.len()),
+    ] {
+        if len != vertex_count {
+            return Err(format!(
+                "{} has {} entries for {} vertices",
+                name, len, vertex_count
+            ));
+        }
+    }
+    if !mesh.indices.len().is_multiple_of(3) {
+        return Err(format!(
+            "{} indices don't form whole triangles",
+            mesh.indices.len()
+        ));
+    }
+    if let Some(index) = mesh
+        .indices
+        .iter()
+        .find(|index| **index as usize >= vertex_count)
+    {
+        return Err(format!(
+            "index {} is out of range for {} vertices",
+            index, vertex_count
+        ));
+    }
+
+    for (vertex, normal) in mesh.normals.iter().enumerate() {
+        let length = Vec3::from_array(*normal).length();
+        if (length - 1.0).abs() > 1e-4 {
+            return Err(format!(
+                "vertex {} has a normal of length {}",
+                vertex, length
+            ));
+        }
+    }
+
+    for (triangle, corners) in mesh.indices.chunks_exact(3).enumerate() {
+        let [a, b, c] = [corners[0], corners[1], corners[2]]
+            .map(|index| Vec3::from_array(mesh.vertices[index as usize]));
+        let face_normal = (b - a).cross(c - a);
+        if face_normal.length_squared() <= f32::EPSILON {
+            return Err(format!("triangle {} is degenerate", triangle));
+        }
+        for index in corners {
+            let normal = Vec3::from_array(mesh.normals[*index as usize]);
+            if face_normal.dot(normal) <= 0.0 {
+                return Err(format!(
+                    "triangle {} is wound against the normal {:?} of vertex {}",
+                    triangle, normal, index
+                ));
             }
-            // Add front face
-            if !terrain_voxels
-                .get(&(*x, *y, z + 1))
-                .map_or(false, |v| v.is_solid)
-                || terrain_voxels
-                    .get(&(*x, *y, *z + 1))
-                    .map_or(false, |v| v.block_type == BlockType::Water)
-            {
-                add_front(&mut mesh, voxel_pos, &voxel.block_type, index_offset);
-                index_offset += 4;
+        }
+    }
+    Ok(())
+}
+
+// Selects how chunk surfaces are turned into quads
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MeshingMode {
+    // One quad per exposed voxel face
+    #[default]
+    Naive,
+    // Coplanar adjacent faces of the same block type are merged into larger quads
+    Greedy,
+}
+
+impl MeshingMode {
+    pub fn next(&self) -> Self {
+        match self {
+            MeshingMode::Naive => MeshingMode::Greedy,
+            MeshingMode::Greedy => MeshingMode::Naive,
+        }
+    }
+}
+
+// Whether faces get darker in the corners next to other blocks
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AmbientOcclusion(pub bool);
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+impl AmbientOcclusion {
+    // Corner occlusion of a face, all open when turned off
+    fn face_ao(
+        self,
+        registry: &BlockRegistry,
+        neighborhood: &ChunkNeighborhood,
+        front_pos: IVec3,
+        normal: IVec3,
+    ) -> [u8; 4] {
+        if self.0 {
+            face_ao(registry, neighborhood, front_pos, normal)
+        } else {
+            [3; 4]
+        }
+    }
+}
+
+pub fn generate_mesh(
+    neighborhood: &ChunkNeighborhood,
+    registry: &BlockRegistry,
+    mode: MeshingMode,
+    ambient_occlusion: AmbientOcclusion,
+) -> ChunkMeshData {
+    // Most chunks above the terrain are empty, skip walking them
+    let center = neighborhood.center();
+    if center.is_uniform() && center.get_voxel(0, 0, 0) == Some(BlockType::AIR) {
+        return ChunkMeshData::new();
+    }
+    let mut meshes = match mode {
+        MeshingMode::Naive => generate_naive_mesh(neighborhood, registry, ambient_occlusion),
+        MeshingMode::Greedy => generate_greedy_mesh(neighborhood, registry, ambient_occlusion),
+    };
+    meshes.connectivity = ChunkConnectivity::compute(center, registry);
+    meshes
+}
+
+pub fn generate_naive_mesh(
+    neighborhood: &ChunkNeighborhood,
+    registry: &BlockRegistry,
+    ambient_occlusion: AmbientOcclusion,
+) -> ChunkMeshData {
+    let mut meshes = ChunkMeshData::new();
+    let chunk = neighborhood.center();
+
+    // Vertices are emitted in chunk local space, the chunk entity's transform places them in the world
+    for x in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_HEIGHT {
+            for z in 0..CHUNK_SIZE {
+                let block_type = match chunk.get_voxel(x, y, z) {
+                    Some(block_type) => block_type,
+                    None => continue,
+                };
+                if block_type == BlockType::AIR {
+                    continue;
+                }
+                let local_pos = IVec3::new(x, y, z);
+                let voxel_pos = Vec3::new(x as f32, y as f32, z as f32);
+                let color = |offset: IVec3| {
+                    lit_color(
+                        registry,
+                        block_type,
+                        face_light(registry, block_type, neighborhood, local_pos + offset),
+                    )
+                };
+                let ao = |offset: IVec3| {
+                    ambient_occlusion.face_ao(registry, neighborhood, local_pos + offset, offset)
+                };
+                let tile = |normal: IVec3| {
+                    atlas::tile_origin(
+                        registry.face_tile(block_type, normal),
+                        registry.tile_count(),
+                    )
+                };
+                let face_visible = |offset: IVec3| {
+                    is_face_visible(
+                        registry,
+                        block_type,
+                        neighborhood.get_voxel(local_pos + offset),
+                    )
+                };
+                let mesh = meshes.layer_mut(registry, block_type);
+
+                // Add top face
+                if face_visible(IVec3::Y) {
+                    let index_offset = mesh.vertices.len() as u32;
+                    add_top(
+                        mesh,
+                        voxel_pos,
+                        color(IVec3::Y),
+                        ao(IVec3::Y),
+                        tile(IVec3::Y),
+                        index_offset,
+                    );
+                }
+                // Add bottom face
+                if face_visible(IVec3::NEG_Y) {
+                    let index_offset = mesh.vertices.len() as u32;
+                    add_bottom(
+                        mesh,
+                        voxel_pos,
+                        color(IVec3::NEG_Y),
+                        ao(IVec3::NEG_Y),
+                        tile(IVec3::NEG_Y),
+                        index_offset,
+                    );
+                }
+                // Add left face
+                if face_visible(IVec3::NEG_X) {
+                    let index_offset = mesh.vertices.len() as u32;
+                    add_left(
+                        mesh,
+                        voxel_pos,
+                        color(IVec3::NEG_X),
+                        ao(IVec3::NEG_X),
+                        tile(IVec3::NEG_X),
+                        index_offset,
+                    );
+                }
+                // Add right face
+                if face_visible(IVec3::X) {
+                    let index_offset = mesh.vertices.len() as u32;
+                    add_right(
+                        mesh,
+                        voxel_pos,
+                        color(IVec3::X),
+                        ao(IVec3::X),
+                        tile(IVec3::X),
+                        index_offset,
+                    );
+                }
+                // Add front face
+                if face_visible(IVec3::Z) {
+                    let index_offset = mesh.vertices.len() as u32;
+                    add_front(
+                        mesh,
+                        voxel_pos,
+                        color(IVec3::Z),
+                        ao(IVec3::Z),
+                        tile(IVec3::Z),
+                        index_offset,
+                    );
+                }
+                // Add back face
+                if face_visible(IVec3::NEG_Z) {
+                    let index_offset = mesh.vertices.len() as u32;
+                    add_back(
+                        mesh,
+                        voxel_pos,
+                        color(IVec3::NEG_Z),
+                        ao(IVec3::NEG_Z),
+                        tile(IVec3::NEG_Z),
+                        index_offset,
+                    );
+                }
             }
-            // Add back face
-            if !terrain_voxels
-                .get(&(*x, *y, z - 1))
-                .map_or(false, |v| v.is_solid)
-                || terrain_voxels
-                    .get(&(*x, *y, *z - 1))
-                    .map_or(false, |v| v.block_type == BlockType::Water)
-            {
-                add_back(&mut mesh, voxel_pos, &voxel.block_type, index_offset);
-                index_offset += 4;
+        }
+    }
+    meshes
+}
+
+// Light reaching a face from the voxel in front of it. Faces against unloaded chunks get
+// daylight, and emissive blocks are never darker than their own light.
+fn face_light(
+    registry: &BlockRegistry,
+    block_type: BlockType,
+    neighborhood: &ChunkNeighborhood,
+    front_pos: IVec3,
+) -> u8 {
+    let light = neighborhood
+        .get_light(front_pos)
+        .unwrap_or(light::pack_light(MAX_LIGHT, 0));
+    let level = LightChannel::Block
+        .level(light)
+        .max(registry.light_emission(block_type));
+    LightChannel::Block.with_level(light, level)
+}
+
+// Block color darkened by the light on the face
+fn lit_color(registry: &BlockRegistry, block_type: BlockType, light: u8) -> [f32; 4] {
+    let [r, g, b, a] = registry.color(block_type);
+    let [tint_r, tint_g, tint_b] = light::light_tint(light);
+    [r * tint_r, g * tint_g, b * tint_b, a]
+}
+
+// A face is drawn when the block in front of it doesn't hide it. Unloaded neighbors count as air.
+// Faces between two blocks of the same type are skipped, so a body of water only shows its
+// surface and the sides facing air.
+fn is_face_visible(
+    registry: &BlockRegistry,
+    block_type: BlockType,
+    neighbor: Option<BlockType>,
+) -> bool {
+    neighbor.is_none_or(|neighbor| neighbor != block_type && registry.is_transparent(neighbor))
+}
+
+pub fn generate_greedy_mesh(
+    neighborhood: &ChunkNeighborhood,
+    registry: &BlockRegistry,
+    ambient_occlusion: AmbientOcclusion,
+) -> ChunkMeshData {
+    let mut meshes = ChunkMeshData::new();
+    let chunk = neighborhood.center();
+    let dims = [CHUNK_SIZE, CHUNK_HEIGHT, CHUNK_SIZE];
+
+    // Sweep each axis in both directions, one slice at a time
+    for axis in 0..3 {
+        // u and v span the slice plane, ordered so that u x v points along +axis
+        let u = (axis + 1) % 3;
+        let v = (axis + 2) % 3;
+        // Faces only merge when their block, light and corner occlusion match
+        let mut mask: Vec<Option<(BlockType, u8, [u8; 4])>> =
+            vec![None; (dims[u] * dims[v]) as usize];
+
+        for positive in [true, false] {
+            let mut normal = IVec3::ZERO;
+            normal[axis] = if positive { 1 } else { -1 };
+
+            for slice in 0..dims[axis] {
+                // Build the mask of visible faces in this slice
+                for j in 0..dims[v] {
+                    for i in 0..dims[u] {
+                        let mut local_pos = IVec3::ZERO;
+                        local_pos[axis] = slice;
+                        local_pos[u] = i;
+                        local_pos[v] = j;
+                        let voxel = chunk.get_voxel(local_pos.x, local_pos.y, local_pos.z);
+                        let neighbor = neighborhood.get_voxel(local_pos + normal);
+                        mask[(j * dims[u] + i) as usize] = match voxel {
+                            Some(block_type)
+                                if block_type != BlockType::AIR
+                                    && is_face_visible(registry, block_type, neighbor) =>
+                            {
+                                Some((
+                                    block_type,
+                                    face_light(
+                                        registry,
+                                        block_type,
+                                        neighborhood,
+                                        local_pos + normal,
+                                    ),
+                                    ambient_occlusion.face_ao(
+                                        registry,
+                                        neighborhood,
+                                        local_pos + normal,
+                                        normal,
+                                    ),
+                                ))
+                            }
+                            _ => None,
+                        };
+                    }
+                }
+
+                // Merge the mask into rectangles, growing along u first and then along v
+                for j in 0..dims[v] {
+                    let mut i = 0;
+                    while i < dims[u] {
+                        let face = match mask[(j * dims[u] + i) as usize] {
+                            Some(face) => face,
+                            None => {
+                                i += 1;
+                                continue;
+                            }
+                        };
+
+                        let mut width = 1;
+                        while i + width < dims[u]
+                            && mask[(j * dims[u] + i + width) as usize] == Some(face)
+                        {
+                            width += 1;
+                        }
+
+                        let mut height = 1;
+                        'grow: while j + height < dims[v] {
+                            for k in 0..width {
+                                if mask[((j + height) * dims[u] + i + k) as usize] != Some(face) {
+                                    break 'grow;
+                                }
+                            }
+                            height += 1;
+                        }
+
+                        for dj in 0..height {
+                            for di in 0..width {
+                                mask[((j + dj) * dims[u] + i + di) as usize] = None;
+                            }
+                        }
+
+                        let mut origin = Vec3::ZERO;
+                        origin[axis] = (if positive { slice + 1 } else { slice }) as f32;
+                        origin[u] = i as f32;
+                        origin[v] = j as f32;
+                        let mut du = Vec3::ZERO;
+                        du[u] = width as f32;
+                        let mut dv = Vec3::ZERO;
+                        dv[v] = height as f32;
+
+                        let (block_type, light, ao) = face;
+                        add_quad(
+                            meshes.layer_mut(registry, block_type),
+                            origin,
+                            du,
+                            dv,
+                            normal.as_vec3(),
+                            lit_color(registry, block_type, light),
+                            ao,
+                            atlas::tile_origin(
+                                registry.face_tile(block_type, normal),
+                                registry.tile_count(),
+                            ),
+                            positive,
+                        );
+                        i += width;
+                    }
+                }
             }
         }
     }
-    return mesh;
+    meshes
 }
 
-fn add_top(mesh: &mut MeshData, voxel_pos: Vec3, block_type: &BlockType, index_offset: u32) {
+// Adds a quad spanning du x dv from origin, wound counter-clockwise when seen from the normal side
+#[allow(clippy::too_many_arguments)]
+fn add_quad(
+    mesh: &mut MeshData,
+    origin: Vec3,
+    du: Vec3,
+    dv: Vec3,
+    normal: Vec3,
+    color: [f32; 4],
+    ao: [u8; 4],
+    tile: [f32; 2],
+    positive: bool,
+) {
+    let index_offset = mesh.vertices.len() as u32;
+    let face_vertices = if positive {
+        [origin, origin + du, origin + du + dv, origin + dv]
+    } else {
+        [origin, origin + dv, origin + du + dv, origin + du]
+    }
+    .map(|vertex| vertex.to_array());
+    mesh.vertices.extend(&face_vertices);
+
+    let (face_colors, flip) = shade_corners(&face_vertices, origin, normal.as_ivec3(), color, ao);
+    mesh.indices.extend(quad_indices(index_offset, flip));
+
+    mesh.normals.extend([normal.to_array(); 4]);
+    mesh.colors.extend(face_colors);
+    mesh.push_face_uvs(normal.as_ivec3(), tile);
+}
+
+// Tangent axes of a face, ordered so that u x v points along +axis like in the greedy mesher
+fn tangent_axes(normal: IVec3) -> (usize, usize) {
+    let axis = if normal.x != 0 {
+        0
+    } else if normal.y != 0 {
+        1
+    } else {
+        2
+    };
+    ((axis + 1) % 3, (axis + 2) % 3)
+}
+
+// Occlusion of a face corner from the three blocks around it in front of the face: 3 is open,
+// 0 is boxed in. With both sides blocked the corner block can't be seen, so it's fully dark.
+fn vertex_ao(side_a: bool, side_b: bool, corner: bool) -> u8 {
+    if side_a && side_b {
+        0
+    } else {
+        3 - (side_a as u8 + side_b as u8 + corner as u8)
+    }
+}
+
+// Ambient occlusion of the four corners of the face whose front voxel is front_pos, indexed by
+// corner as u + 2 * v, 0 for the low and 1 for the high side of each tangent axis
+fn face_ao(
+    registry: &BlockRegistry,
+    neighborhood: &ChunkNeighborhood,
+    front_pos: IVec3,
+    normal: IVec3,
+) -> [u8; 4] {
+    let (u, v) = tangent_axes(normal);
+    let occludes = |pos: IVec3| {
+        neighborhood
+            .get_voxel(pos)
+            .is_some_and(|block_type| !registry.is_transparent(block_type))
+    };
+    std::array::from_fn(|corner| {
+        let mut side_u = IVec3::ZERO;
+        side_u[u] = if corner & 1 == 1 { 1 } else { -1 };
+        let mut side_v = IVec3::ZERO;
+        side_v[v] = if corner & 2 == 2 { 1 } else { -1 };
+        vertex_ao(
+            occludes(front_pos + side_u),
+            occludes(front_pos + side_v),
+            occludes(front_pos + side_u + side_v),
+        )
+    })
+}
+
+// Brightness of each ambient occlusion level
+const AO_BRIGHTNESS: [f32; 4] = [0.45, 0.6, 0.78, 1.0];
+
+// Colors of a quad's vertices darkened by the occlusion of their corner, and whether to split
+// it along its other diagonal. The split runs between the brighter pair of corners, otherwise a
+// single dark corner smears across both triangles and the shading changes with the quad's
+// orientation. min is the quad's lowest corner.
+fn shade_corners(
+    vertices: &[[f32; 3]; 4],
+    min: Vec3,
+    normal: IVec3,
+    color: [f32; 4],
+    ao: [u8; 4],
+) -> ([[f32; 4]; 4], bool) {
+    let (u, v) = tangent_axes(normal);
+    let levels = vertices.map(|vertex| {
+        let vertex = Vec3::from_array(vertex);
+        let corner = (vertex[u] > min[u]) as usize | ((vertex[v] > min[v]) as usize) << 1;
+        ao[corner]
+    });
+    let colors = levels.map(|level| {
+        let shade = AO_BRIGHTNESS[level.min(3) as usize];
+        [
+            color[0] * shade,
+            color[1] * shade,
+            color[2] * shade,
+            color[3],
+        ]
+    });
+    let flip = levels[0] + levels[2] < levels[1] + levels[3];
+    (colors, flip)
+}
+
+// Two triangles covering a quad, split along 0-2 or along 1-3 when flipped. Both keep the
+// winding of the vertices.
+fn quad_indices(index_offset: u32, flip: bool) -> [u32; 6] {
+    let order = if flip {
+        [1, 2, 3, 3, 0, 1]
+    } else {
+        [0, 1, 2, 2, 3, 0]
+    };
+    order.map(|i| i + index_offset)
+}
+
+fn add_top(
+    mesh: &mut MeshData,
+    voxel_pos: Vec3,
+    color: [f32; 4],
+    ao: [u8; 4],
+    tile: [f32; 2],
+    index_offset: u32,
+) {
     let x = voxel_pos.x;
     let y = voxel_pos.y;
     let z = voxel_pos.z;
 
-    let face_vertices = vec![
-        [x + 0.0, y + 1.0, z + 1.0], // 0
-        [x + 0.0, y + 1.0, z + 0.0], // 1 // Top face
-        [x + 1.0, y + 1.0, z + 0.0], // 2
-        [x + 1.0, y + 1.0, z + 1.0], // 3
+    let face_vertices = [
+        [x + 1.0, y + 1.0, z + 1.0], // 0
+        [x + 1.0, y + 1.0, z + 0.0], // 1 // Top face
+        [x + 0.0, y + 1.0, z + 0.0], // 2
+        [x + 0.0, y + 1.0, z + 1.0], // 3
     ];
-    mesh.vertices.extend(face_vertices);
+    mesh.vertices.extend(&face_vertices);
+    mesh.push_face_uvs(IVec3::Y, tile);
 
-    let face_indices: Vec<u32> = vec![0, 1, 2, 2, 3, 0]
-        .into_iter()
-        .map(|i| i + index_offset)
-        .collect();
-    mesh.indices.extend(face_indices);
+    let (face_colors, flip) = shade_corners(&face_vertices, voxel_pos, IVec3::Y, color, ao);
+    mesh.indices.extend(quad_indices(index_offset, flip));
 
     let face_normals = vec![[0.0, 1.0, 0.0]; 4];
     mesh.normals.extend(&face_normals);
 
-    let _test_color: [[f32; 4]; 4] = [[1.0, 0.0, 0.0, 1.0]; 4];
-
-    let face_colors = vec![block_type.color(); 4];
     mesh.colors.extend(face_colors);
 }
 
-fn add_bottom(mesh: &mut MeshData, voxel_pos: Vec3, block_type: &BlockType, index_offset: u32) {
+fn add_bottom(
+    mesh: &mut MeshData,
+    voxel_pos: Vec3,
+    color: [f32; 4],
+    ao: [u8; 4],
+    tile: [f32; 2],
+    index_offset: u32,
+) {
     let x = voxel_pos.x;
     let y = voxel_pos.y;
     let z = voxel_pos.z;
 
-    let face_vertices = vec![
+    let face_vertices = [
         [x + 1.0, y + 0.0, z + 1.0], // 4
         [x + 0.0, y + 0.0, z + 1.0], // 5 // Bottom face
         [x + 0.0, y + 0.0, z + 0.0], // 6
         [x + 1.0, y + 0.0, z + 0.0], // 7
     ];
-    mesh.vertices.extend(face_vertices);
+    mesh.vertices.extend(&face_vertices);
+    mesh.push_face_uvs(IVec3::NEG_Y, tile);
 
-    let face_indices: Vec<u32> = vec![0, 1, 2, 2, 3, 0]
-        .into_iter()
-        .map(|i| i + index_offset)
-        .collect();
-    mesh.indices.extend(face_indices);
+    let (face_colors, flip) = shade_corners(&face_vertices, voxel_pos, IVec3::NEG_Y, color, ao);
+    mesh.indices.extend(quad_indices(index_offset, flip));
 
     let face_normals = vec![[0.0, -1.0, 0.0]; 4];
     mesh.normals.extend(&face_normals);
 
-    let _test_color: [[f32; 4]; 4] = [[1.0, 0.0, 0.0, 1.0]; 4];
-
-    let face_colors = vec![block_type.color(); 4];
     mesh.colors.extend(face_colors);
 }
 
-fn add_left(mesh: &mut MeshData, voxel_pos: Vec3, block_type: &BlockType, index_offset: u32) {
+fn add_left(
+    mesh: &mut MeshData,
+    voxel_pos: Vec3,
+    color: [f32; 4],
+    ao: [u8; 4],
+    tile: [f32; 2],
+    index_offset: u32,
+) {
     let x = voxel_pos.x;
     let y = voxel_pos.y;
     let z = voxel_pos.z;
 
-    let face_vertices = vec![
+    let face_vertices = [
         [x + 0.0, y + 0.0, z + 1.0], // 8
         [x + 0.0, y + 1.0, z + 1.0], // 9 // Left face
         [x + 0.0, y + 1.0, z + 0.0], // 10
         [x + 0.0, y + 0.0, z + 0.0], // 11
     ];
-    mesh.vertices.extend(face_vertices);
+    mesh.vertices.extend(&face_vertices);
+    mesh.push_face_uvs(IVec3::NEG_X, tile);
 
-    let face_indices: Vec<u32> = vec![0, 1, 2, 2, 3, 0]
-        .into_iter()
-        .map(|i| i + index_offset)
-        .collect();
-    mesh.indices.extend(face_indices);
+    let (face_colors, flip) = shade_corners(&face_vertices, voxel_pos, IVec3::NEG_X, color, ao);
+    mesh.indices.extend(quad_indices(index_offset, flip));
 
     let face_normals = vec![[-1.0, 0.0, 0.0]; 4];
     mesh.normals.extend(&face_normals);
 
-    let _test_color: [[f32; 4]; 4] = [[1.0, 0.0, 0.0, 1.0]; 4];
-
-    let face_colors = vec![block_type.color(); 4];
     mesh.colors.extend(face_colors);
 }
 
-fn add_right(mesh: &mut MeshData, voxel_pos: Vec3, block_type: &BlockType, index_offset: u32) {
+fn add_right(
+    mesh: &mut MeshData,
+    voxel_pos: Vec3,
+    color: [f32; 4],
+    ao: [u8; 4],
+    tile: [f32; 2],
+    index_offset: u32,
+) {
     let x = voxel_pos.x;
     let y = voxel_pos.y;
     let z = voxel_pos.z;
 
-    let face_vertices = vec![
-        [x + 1.0, y + 0.0, z + 1.0], // 12
-        [x + 1.0, y + 1.0, z + 1.0], // 13 // Right face
-        [x + 1.0, y + 1.0, z + 0.0], // 14
-        [x + 1.0, y + 0.0, z + 0.0], // 15
+    let face_vertices = [
+        [x + 1.0, y + 0.0, z + 0.0], // 12
+        [x + 1.0, y + 1.0, z + 0.0], // 13 // Right face
+        [x + 1.0, y + 1.0, z + 1.0], // 14
+        [x + 1.0, y + 0.0, z + 1.0], // 15
     ];
-    mesh.vertices.extend(face_vertices);
+    mesh.vertices.extend(&face_vertices);
+    mesh.push_face_uvs(IVec3::X, tile);
 
-    let face_indices: Vec<u32> = vec![0, 1, 2, 2, 3, 0]
-        .into_iter()
-        .map(|i| i + index_offset)
-        .collect();
-    mesh.indices.extend(face_indices);
+    let (face_colors, flip) = shade_corners(&face_vertices, voxel_pos, IVec3::X, color, ao);
+    mesh.indices.extend(quad_indices(index_offset, flip));
 
-    let face_normals = vec![[-1.0, 0.0, 0.0]; 4];
+    let face_normals = vec![[1.0, 0.0, 0.0]; 4];
     mesh.normals.extend(&face_normals);
 
-    let _test_color: [[f32; 4]; 4] = [[1.0, 0.0, 0.0, 1.0]; 4];
-
-    let face_colors = vec![block_type.color(); 4];
     mesh.colors.extend(face_colors);
 }
 
-fn add_front(mesh: &mut MeshData, voxel_pos: Vec3, block_type: &BlockType, index_offset: u32) {
+fn add_front(
+    mesh: &mut MeshData,
+    voxel_pos: Vec3,
+    color: [f32; 4],
+    ao: [u8; 4],
+    tile: [f32; 2],
+    index_offset: u32,
+) {
     let x = voxel_pos.x;
     let y = voxel_pos.y;
     let z = voxel_pos.z;
 
-    let face_vertices = vec![
-        [x + 0.0, y + 0.0, z + 1.0], // 16
-        [x + 0.0, y + 1.0, z + 1.0], // 17 // Front face
-        [x + 1.0, y + 1.0, z + 1.0], // 18
-        [x + 1.0, y + 0.0, z + 1.0], // 19
+    let face_vertices = [
+        [x + 1.0, y + 0.0, z + 1.0], // 16
+        [x + 1.0, y + 1.0, z + 1.0], // 17 // Front face
+        [x + 0.0, y + 1.0, z + 1.0], // 18
+        [x + 0.0, y + 0.0, z + 1.0], // 19
     ];
-    mesh.vertices.extend(face_vertices);
+    mesh.vertices.extend(&face_vertices);
+    mesh.push_face_uvs(IVec3::Z, tile);
 
-    let face_indices: Vec<u32> = vec![0, 1, 2, 2, 3, 0]
-        .into_iter()
-        .map(|i| i + index_offset)
-        .collect();
-    mesh.indices.extend(face_indices);
+    let (face_colors, flip) = shade_corners(&face_vertices, voxel_pos, IVec3::Z, color, ao);
+    mesh.indices.extend(quad_indices(index_offset, flip));
 
-    let face_normals = vec![[-1.0, 0.0, 0.0]; 4];
+    let face_normals = vec![[0.0, 0.0, 1.0]; 4];
     mesh.normals.extend(&face_normals);
 
-    let _test_color: [[f32; 4]; 4] = [[1.0, 0.0, 0.0, 1.0]; 4];
-
-    let face_colors = vec![block_type.color(); 4];
     mesh.colors.extend(face_colors);
 }
 
-fn add_back(mesh: &mut MeshData, voxel_pos: Vec3, block_type: &BlockType, index_offset: u32) {
+fn add_back(
+    mesh: &mut MeshData,
+    voxel_pos: Vec3,
+    color: [f32; 4],
+    ao: [u8; 4],
+    tile: [f32; 2],
+    index_offset: u32,
+) {
     let x = voxel_pos.x;
     let y = voxel_pos.y;
     let z = voxel_pos.z;
 
-    let face_vertices = vec![
+    let face_vertices = [
         [x + 0.0, y + 0.0, z + 0.0], // 20
         [x + 0.0, y + 1.0, z + 0.0], // 21 // Back face
         [x + 1.0, y + 1.0, z + 0.0], // 22
         [x + 1.0, y + 0.0, z + 0.0], // 23
     ];
-    mesh.vertices.extend(face_vertices);
+    mesh.vertices.extend(&face_vertices);
+    mesh.push_face_uvs(IVec3::NEG_Z, tile);
 
-    let face_indices: Vec<u32> = vec![0, 1, 2, 2, 3, 0]
-        .into_iter()
-        .map(|i| i + index_offset)
-        .collect();
-    mesh.indices.extend(face_indices);
+    let (face_colors, flip) = shade_corners(&face_vertices, voxel_pos, IVec3::NEG_Z, color, ao);
+    mesh.indices.extend(quad_indices(index_offset, flip));
 
-    let face_normals = vec![[-1.0, 0.0, 0.0]; 4];
+    let face_normals = vec![[0.0, 0.0, -1.0]; 4];
     mesh.normals.extend(&face_normals);
 
-    let _test_color: [[f32; 4]; 4] = [[1.0, 0.0, 0.0, 1.0]; 4];
-
-    let face_colors = vec![block_type.color(); 4];
     mesh.colors.extend(face_colors);
 }
 
-fn add_voxel_cube(mesh: &mut MeshData, voxel_pos: Vec3, block_type: &BlockType, index_offset: u32) {
+fn add_voxel_cube(
+    mesh: &mut MeshData,
+    voxel_pos: Vec3,
+    color: [f32; 4],
+    tile: [f32; 2],
+    index_offset: u32,
+) {
     let cube_vertices = generate_cube_vertices(voxel_pos);
     let cube_indices = generate_cube_indices(index_offset);
     let cube_normals = generate_cube_normals();
@@ -296,9 +856,14 @@ fn add_voxel_cube(mesh: &mut MeshData, voxel_pos: Vec3, block_type: &BlockType,
     for normal in &cube_normals {
         mesh.normals.extend([*normal; 4]);
     }
-    let face_colors = vec![block_type.color(); 4];
-
-    mesh.colors.extend(face_colors);
+    for (face, normal) in cube_normals.iter().enumerate() {
+        for vertex in &mesh.vertices[mesh.vertices.len() - 24 + face * 4..][..4] {
+            mesh.uvs
+                .push(face_uv(*vertex, Vec3::from_array(*normal).as_ivec3()));
+        }
+    }
+    mesh.tile_uvs.extend([tile; 24]);
+    mesh.colors.extend([color; 24]);
 }
 
 pub fn generate_cube_vertices(pos: Vec3) -> Vec<[f32; 3]> {
@@ -307,10 +872,10 @@ pub fn generate_cube_vertices(pos: Vec3) -> Vec<[f32; 3]> {
     let z = pos.z;
 
     vec![
-        [x + 0.0, y + 1.0, z + 1.0], // 0
-        [x + 0.0, y + 1.0, z + 0.0], // 1 // Top face
-        [x + 1.0, y + 1.0, z + 0.0], // 2
-        [x + 1.0, y + 1.0, z + 1.0], // 3
+        [x + 1.0, y + 1.0, z + 1.0], // 0
+        [x + 1.0, y + 1.0, z + 0.0], // 1 // Top face
+        [x + 0.0, y + 1.0, z + 0.0], // 2
+        [x + 0.0, y + 1.0, z + 1.0], // 3
         [x + 1.0, y + 0.0, z + 1.0], // 4
         [x + 0.0, y + 0.0, z + 1.0], // 5 // Bottom face
         [x + 0.0, y + 0.0, z + 0.0], // 6
@@ -319,14 +884,14 @@ pub fn generate_cube_vertices(pos: Vec3) -> Vec<[f32; 3]> {
         [x + 0.0, y + 1.0, z + 1.0], // 9 // Left face
         [x + 0.0, y + 1.0, z + 0.0], // 10
         [x + 0.0, y + 0.0, z + 0.0], // 11
-        [x + 1.0, y + 0.0, z + 1.0], // 12
-        [x + 1.0, y + 1.0, z + 1.0], // 13 // Right face
-        [x + 1.0, y + 1.0, z + 0.0], // 14
-        [x + 1.0, y + 0.0, z + 0.0], // 15
-        [x + 0.0, y + 0.0, z + 1.0], // 16
-        [x + 0.0, y + 1.0, z + 1.0], // 17 // Front face
-        [x + 1.0, y + 1.0, z + 1.0], // 18
-        [x + 1.0, y + 0.0, z + 1.0], // 19
+        [x + 1.0, y + 0.0, z + 0.0], // 12
+        [x + 1.0, y + 1.0, z + 0.0], // 13 // Right face
+        [x + 1.0, y + 1.0, z + 1.0], // 14
+        [x + 1.0, y + 0.0, z + 1.0], // 15
+        [x + 1.0, y + 0.0, z + 1.0], // 16
+        [x + 1.0, y + 1.0, z + 1.0], // 17 // Front face
+        [x + 0.0, y + 1.0, z + 1.0], // 18
+        [x + 0.0, y + 0.0, z + 1.0], // 19
         [x + 0.0, y + 0.0, z + 0.0], // 20
         [x + 0.0, y + 1.0, z + 0.0], // 21 // Back face
         [x + 1.0, y + 1.0, z + 0.0], // 22
@@ -364,3 +929,233 @@ pub fn generate_cube_normals() -> Vec<[f32; 3]> {
         [0.0, 0.0, -1.0],
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{block::ChunkMap, settings::TerrainSettings, terrain::TerrainGenerator};
+
+    type FaceFn = fn(&mut MeshData, Vec3, [f32; 4], [u8; 4], [f32; 2], u32);
+
+    const WHITE: [f32; 4] = [1.0; 4];
+    const TILE: [f32; 2] = [0.0; 2];
+    const OPEN: [u8; 4] = [3; 4];
+
+    fn face_fns() -> [(&'static str, FaceFn, IVec3); 6] {
+        [
+            ("top", add_top, IVec3::Y),
+            ("bottom", add_bottom, IVec3::NEG_Y),
+            ("left", add_left, IVec3::NEG_X),
+            ("right", add_right, IVec3::X),
+            ("front", add_front, IVec3::Z),
+            ("back", add_back, IVec3::NEG_Z),
+        ]
+    }
+
+    #[test]
+    fn face_functions_produce_valid_faces() {
+        let voxel_pos = Vec3::new(3.0, 5.0, 7.0);
+        for (name, add_face, normal) in face_fns() {
+            let mut mesh = MeshData::new();
+            add_face(&mut mesh, voxel_pos, WHITE, OPEN, TILE, 0);
+            assert_eq!(validate_mesh(&mesh), Ok(()), "{} face", name);
+            assert_eq!(mesh.vertices.len(), 4, "{} face", name);
+            assert_eq!(mesh.indices.len(), 6, "{} face", name);
+            assert!(
+                mesh.normals
+                    .iter()
+                    .all(|n| *n == normal.as_vec3().to_array()),
+                "{} face normals {:?}",
+                name,
+                mesh.normals
+            );
+        }
+    }
+
+    #[test]
+    fn face_functions_lie_on_their_side_of_the_voxel() {
+        let voxel_pos = Vec3::new(3.0, 5.0, 7.0);
+        let center = voxel_pos + Vec3::splat(0.5);
+        for (name, add_face, normal) in face_fns() {
+            let mut mesh = MeshData::new();
+            add_face(&mut mesh, voxel_pos, WHITE, OPEN, TILE, 0);
+            let normal = normal.as_vec3();
+            for vertex in &mesh.vertices {
+                let offset = Vec3::from_array(*vertex) - center;
+                assert_eq!(offset.dot(normal), 0.5, "{} face vertex {:?}", name, vertex);
+            }
+        }
+    }
+
+    #[test]
+    fn face_functions_offset_indices() {
+        for (name, add_face, _) in face_fns() {
+            let mut mesh = MeshData::new();
+            add_face(&mut mesh, Vec3::ZERO, WHITE, OPEN, TILE, 0);
+            add_face(&mut mesh, Vec3::X, WHITE, OPEN, TILE, 4);
+            assert_eq!(validate_mesh(&mesh), Ok(()), "{} face", name);
+            assert!(mesh.indices[6..].iter().all(|index| *index >= 4));
+        }
+    }
+
+    #[test]
+    fn voxel_cube_is_valid() {
+        let mut mesh = MeshData::new();
+        add_voxel_cube(&mut mesh, Vec3::new(1.0, 2.0, 3.0), WHITE, TILE, 0);
+        assert_eq!(validate_mesh(&mesh), Ok(()));
+        assert_eq!(mesh.vertices.len(), 24);
+    }
+
+    #[test]
+    fn quads_are_valid_on_every_axis() {
+        for axis in 0..3 {
+            let u = (axis + 1) % 3;
+            let v = (axis + 2) % 3;
+            for positive in [true, false] {
+                let mut normal = Vec3::ZERO;
+                normal[axis] = if positive { 1.0 } else { -1.0 };
+                let mut du = Vec3::ZERO;
+                du[u] = 3.0;
+                let mut dv = Vec3::ZERO;
+                dv[v] = 2.0;
+
+                let mut mesh = MeshData::new();
+                add_quad(
+                    &mut mesh,
+                    Vec3::ONE,
+                    du,
+                    dv,
+                    normal,
+                    WHITE,
+                    OPEN,
+                    TILE,
+                    positive,
+                );
+                assert_eq!(validate_mesh(&mesh), Ok(()), "normal {:?}", normal);
+            }
+        }
+    }
+
+    #[test]
+    fn validation_rejects_broken_meshes() {
+        let mut mesh = MeshData::new();
+        add_top(&mut mesh, Vec3::ZERO, WHITE, OPEN, TILE, 0);
+
+        let mut flipped = MeshData::new();
+        flipped.insert_mesh(&mesh);
+        flipped.indices.swap(1, 2);
+        assert!(validate_mesh(&flipped).is_err());
+
+        let mut out_of_range = MeshData::new();
+        out_of_range.insert_mesh(&mesh);
+        out_of_range.indices[0] = 4;
+        assert!(validate_mesh(&out_of_range).is_err());
+
+        let mut missing_color = MeshData::new();
+        missing_color.insert_mesh(&mesh);
+        missing_color.colors.pop();
+        assert!(validate_mesh(&missing_color).is_err());
+
+        let mut partial_triangle = MeshData::new();
+        partial_triangle.insert_mesh(&mesh);
+        partial_triangle.indices.pop();
+        assert!(validate_mesh(&partial_triangle).is_err());
+    }
+
+    #[test]
+    fn generated_chunk_meshes_are_valid() {
+        let registry = BlockRegistry::default();
+        let generator = TerrainGenerator::new(7, &registry, &TerrainSettings::default());
+        let mut chunk_map = ChunkMap::new();
+        for chunk_pos in [IVec3::ZERO, IVec3::X, IVec3::NEG_Z] {
+            chunk_map.insert_chunk(chunk_pos, generator.generate_chunk(chunk_pos));
+        }
+        let neighborhood = chunk_map.neighborhood(IVec3::ZERO).unwrap();
+
+        for mode in [MeshingMode::Naive, MeshingMode::Greedy] {
+            let meshes = generate_mesh(&neighborhood, &registry, mode, AmbientOcclusion(true));
+            assert!(!meshes.opaque.indices.is_empty());
+            assert_eq!(validate_mesh(&meshes.opaque), Ok(()), "{:?} opaque", mode);
+            assert_eq!(
+                validate_mesh(&meshes.translucent),
+                Ok(()),
+                "{:?} translucent",
+                mode
+            );
+        }
+    }
+
+    // Top faces of a stone floor at y = 0 with the given blocks standing on it
+    fn floor_with(blocks: &[IVec3], mode: MeshingMode, ao: bool) -> MeshData {
+        let registry = BlockRegistry::default();
+        let stone = registry.id("stone").unwrap();
+        let voxels: Vec<BlockType> = crate::coords::local_positions()
+            .map(|local_pos| {
+                if local_pos.y == 0 || blocks.contains(&local_pos) {
+                    stone
+                } else {
+                    BlockType::AIR
+                }
+            })
+            .collect();
+        let mut chunk_map = ChunkMap::new();
+        chunk_map.insert_chunk(IVec3::ZERO, crate::block::Chunk::from_blocks(&voxels));
+        let neighborhood = chunk_map.neighborhood(IVec3::ZERO).unwrap();
+        generate_mesh(&neighborhood, &registry, mode, AmbientOcclusion(ao)).opaque
+    }
+
+    // Colors of the top face vertices at the given corner of the floor
+    fn floor_colors(mesh: &MeshData, corner: [f32; 2]) -> Vec<[f32; 4]> {
+        (0..mesh.vertices.len())
+            .filter(|index| {
+                let [x, y, z] = mesh.vertices[*index];
+                y == 1.0 && [x, z] == corner && mesh.normals[*index] == [0.0, 1.0, 0.0]
+            })
+            .map(|index| mesh.colors[index])
+            .collect()
+    }
+
+    #[test]
+    fn corners_next_to_blocks_are_darker() {
+        assert_eq!(vertex_ao(false, false, false), 3);
+        assert_eq!(vertex_ao(false, false, true), 2);
+        assert_eq!(vertex_ao(true, false, true), 1);
+        assert_eq!(vertex_ao(true, true, false), 0);
+
+        let wall = [IVec3::new(5, 1, 5)];
+        for mode in [MeshingMode::Naive, MeshingMode::Greedy] {
+            let mesh = floor_with(&wall, mode, true);
+            assert_eq!(validate_mesh(&mesh), Ok(()), "{:?}", mode);
+            let base = BlockRegistry::default().color(BlockRegistry::default().id_or_air("stone"));
+            // Next to the block the floor is darker, a block away it's untouched
+            let next_to = floor_colors(&mesh, [5.0, 5.0]);
+            assert!(!next_to.is_empty());
+            assert!(next_to.iter().all(|color| color[0] < base[0]), "{:?}", mode);
+            let open = floor_colors(&mesh, [3.0, 3.0]);
+            assert!(open.iter().all(|color| color[0] == base[0]), "{:?}", mode);
+
+            // Turned off, every corner is the same
+            let mesh = floor_with(&wall, mode, false);
+            let next_to = floor_colors(&mesh, [5.0, 5.0]);
+            assert!(
+                next_to.iter().all(|color| color[0] == base[0]),
+                "{:?}",
+                mode
+            );
+        }
+    }
+
+    #[test]
+    fn quads_split_away_from_a_dark_corner() {
+        let ao = [3, 3, 3, 0];
+        for (name, add_face, _) in face_fns() {
+            let mut mesh = MeshData::new();
+            add_face(&mut mesh, Vec3::ZERO, WHITE, ao, TILE, 0);
+            assert_eq!(validate_mesh(&mesh), Ok(()), "{} face", name);
+            // The dark vertex belongs to a single triangle instead of lying on the diagonal
+            let dark = (0..4).find(|index| mesh.colors[*index][0] < 1.0).unwrap() as u32;
+            let uses = mesh.indices.iter().filter(|index| **index == dark).count();
+            assert_eq!(uses, 1, "{} face", name);
+        }
+    }
+}
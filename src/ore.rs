@@ -0,0 +1,223 @@
+#![allow(dead_code)]
+use crate::{
+    block::{BlockType, CHUNK_SIZE},
+    coords,
+    terrain::{position_chance, position_hash, TerrainBlocks},
+};
+use bevy::prelude::*;
+
+// One kind of ore and where its veins show up
+#[derive(Debug, Clone, Copy)]
+pub struct OreVein {
+    pub block: BlockType,
+    // World y range vein origins are picked in
+    pub min_y: i32,
+    pub max_y: i32,
+    // Average number of veins started per chunk, fractions give a chance of one more
+    pub veins_per_chunk: f32,
+    // Steps of the random walk, each step places a small blob of ore
+    pub vein_size: i32,
+}
+
+// Veins start in their own chunk but wander up to this far from their origin
+const MAX_VEIN_SIZE: i32 = CHUNK_SIZE - 2;
+
+#[derive(Debug, Clone)]
+pub struct OreSet {
+    pub ores: Vec<OreVein>,
+}
+
+impl OreSet {
+    pub fn new(blocks: &TerrainBlocks) -> Self {
+        let ores = vec![
+            OreVein {
+                block: blocks.coal_ore,
+                min_y: -96,
+                max_y: 128,
+                veins_per_chunk: 8.0,
+                vein_size: 10,
+            },
+            OreVein {
+                block: blocks.iron_ore,
+                min_y: -160,
+                max_y: 48,
+                veins_per_chunk: 5.0,
+                vein_size: 6,
+            },
+            OreVein {
+                block: blocks.gold_ore,
+                min_y: -256,
+                max_y: 0,
+                veins_per_chunk: 1.5,
+                vein_size: 5,
+            },
+        ];
+        Self { ores }
+    }
+
+    // Replaces stone in the chunk's voxels with ore. Veins come from this chunk and all its
+    // neighbors, but only voxels inside this chunk are written, and every vein is worked out
+    // from the seed and its origin chunk alone. So a vein crossing a chunk border is the same on
+    // both sides whichever chunk generates first.
+    pub fn place_ores(
+        &self,
+        seed: u64,
+        chunk_pos: IVec3,
+        stone: BlockType,
+        voxels: &mut [BlockType],
+    ) {
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let origin_chunk = chunk_pos + IVec3::new(x, y, z);
+                    for (kind, ore) in self.ores.iter().enumerate() {
+                        self.place_chunk_veins(
+                            seed,
+                            origin_chunk,
+                            chunk_pos,
+                            kind,
+                            ore,
+                            stone,
+                            voxels,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn place_chunk_veins(
+        &self,
+        seed: u64,
+        origin_chunk: IVec3,
+        chunk_pos: IVec3,
+        kind: usize,
+        ore: &OreVein,
+        stone: BlockType,
+        voxels: &mut [BlockType],
+    ) {
+        // Every ore kind gets its own stream of numbers for the same chunk
+        let ore_seed = seed ^ (kind as u64 + 1).wrapping_mul(0xA24B_AED4_963E_E407);
+        let mut count = ore.veins_per_chunk as i32;
+        if position_chance(ore_seed, origin_chunk) < ore.veins_per_chunk.fract() {
+            count += 1;
+        }
+
+        let chunk_seed = position_hash(ore_seed, origin_chunk);
+        for vein in 0..count {
+            let vein_seed = position_hash(chunk_seed, IVec3::new(vein, 0, 0));
+            let local = IVec3::new(
+                (position_hash(vein_seed, IVec3::X) % CHUNK_SIZE as u64) as i32,
+                (position_hash(vein_seed, IVec3::Y) % CHUNK_SIZE as u64) as i32,
+                (position_hash(vein_seed, IVec3::Z) % CHUNK_SIZE as u64) as i32,
+            );
+            let start = coords::local_to_world(origin_chunk, local);
+            if start.y < ore.min_y || start.y > ore.max_y {
+                continue;
+            }
+
+            let mut position = start.as_vec3();
+            let mut direction = random_direction(vein_seed, 0);
+            for step in 0..ore.vein_size.min(MAX_VEIN_SIZE) {
+                let center = position.round().as_ivec3();
+                // The blob: its center and a random half of the six blocks around it
+                let blob_seed = position_hash(vein_seed, IVec3::new(step, 0, 0));
+                for (face, offset) in [
+                    IVec3::ZERO,
+                    IVec3::X,
+                    IVec3::NEG_X,
+                    IVec3::Y,
+                    IVec3::NEG_Y,
+                    IVec3::Z,
+                    IVec3::NEG_Z,
+                ]
+                .into_iter()
+                .enumerate()
+                {
+                    if face > 0 && (blob_seed >> face) & 1 == 0 {
+                        continue;
+                    }
+                    let world_pos = center + offset;
+                    if coords::world_to_chunk(world_pos) != chunk_pos {
+                        continue;
+                    }
+                    let index = coords::local_to_index(coords::world_to_local(world_pos)).unwrap();
+                    if voxels[index] == stone {
+                        voxels[index] = ore.block;
+                    }
+                }
+                // Wander off, bending a little each step
+                direction = (direction + random_direction(vein_seed, step + 1) * 0.5)
+                    .try_normalize()
+                    .unwrap_or(direction);
+                position += direction;
+            }
+        }
+    }
+}
+
+// Roughly uniform unit vector picked from the seed
+fn random_direction(seed: u64, step: i32) -> Vec3 {
+    let direction = Vec3::new(
+        position_chance(seed, IVec3::new(step, 1, 0)) * 2.0 - 1.0,
+        position_chance(seed, IVec3::new(step, 2, 0)) * 2.0 - 1.0,
+        position_chance(seed, IVec3::new(step, 3, 0)) * 2.0 - 1.0,
+    );
+    direction.try_normalize().unwrap_or(Vec3::X)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{block::CHUNK_VOLUME, registry::BlockRegistry};
+
+    fn ore_counts(ores: &OreSet, voxels: &[BlockType]) -> Vec<usize> {
+        ores.ores
+            .iter()
+            .map(|ore| voxels.iter().filter(|block| **block == ore.block).count())
+            .collect()
+    }
+
+    #[test]
+    fn ores_replace_stone_within_their_depth() {
+        let blocks = TerrainBlocks::from_registry(&BlockRegistry::default());
+        let ores = OreSet::new(&blocks);
+
+        // Deep down every ore has room
+        let chunk_pos = IVec3::new(3, -3, -5);
+        let mut voxels = vec![blocks.stone; CHUNK_VOLUME];
+        ores.place_ores(7, chunk_pos, blocks.stone, &mut voxels);
+        let counts = ore_counts(&ores, &voxels);
+        assert!(counts.iter().all(|count| *count > 0), "{:?}", counts);
+
+        // Nothing but stone is replaced
+        let mut voxels = vec![blocks.dirt; CHUNK_VOLUME];
+        ores.place_ores(7, chunk_pos, blocks.stone, &mut voxels);
+        assert!(voxels.iter().all(|block| *block == blocks.dirt));
+
+        // High up no vein can reach
+        let mut voxels = vec![blocks.stone; CHUNK_VOLUME];
+        ores.place_ores(7, IVec3::new(3, 8, -5), blocks.stone, &mut voxels);
+        assert!(voxels.iter().all(|block| *block == blocks.stone));
+    }
+
+    #[test]
+    fn ores_depend_only_on_seed_and_chunk() {
+        let blocks = TerrainBlocks::from_registry(&BlockRegistry::default());
+        let ores = OreSet::new(&blocks);
+        let place = |seed: u64, chunk_pos: IVec3| {
+            let mut voxels = vec![blocks.stone; CHUNK_VOLUME];
+            ores.place_ores(seed, chunk_pos, blocks.stone, &mut voxels);
+            voxels
+        };
+
+        let chunk_pos = IVec3::new(-1, -2, 4);
+        let first = place(11, chunk_pos);
+        // Other chunks in between change nothing, there is no shared state
+        place(11, chunk_pos + IVec3::X);
+        place(11, chunk_pos - IVec3::Y);
+        assert_eq!(place(11, chunk_pos), first);
+        assert_ne!(place(12, chunk_pos), first);
+    }
+}
@@ -0,0 +1,309 @@
+#![allow(dead_code)]
+use crate::block::BlockType;
+use std::mem::size_of;
+
+// Block storage for a chunk: a small palette of the block types in use plus one bit-packed
+// palette index per voxel. Chunks made of a single block type skip the index array entirely.
+#[derive(Debug, Clone)]
+pub enum PaletteStorage {
+    Uniform {
+        len: usize,
+        block_type: BlockType,
+    },
+    Paletted {
+        len: usize,
+        palette: Vec<BlockType>,
+        // Bits per packed index, indices never straddle two words
+        bits: u32,
+        data: Vec<u64>,
+    },
+}
+
+impl PaletteStorage {
+    pub fn uniform(len: usize, block_type: BlockType) -> Self {
+        PaletteStorage::Uniform { len, block_type }
+    }
+
+    // Packs a flat list of blocks, choosing the smallest palette that fits
+    pub fn from_blocks(blocks: &[BlockType]) -> Self {
+        let mut palette: Vec<BlockType> = Vec::new();
+        for block_type in blocks {
+            if !palette.contains(block_type) {
+                palette.push(*block_type);
+            }
+        }
+        if palette.len() <= 1 {
+            return Self::uniform(
+                blocks.len(),
+                palette.first().copied().unwrap_or(BlockType::AIR),
+            );
+        }
+
+        let bits = bits_for(palette.len());
+        let mut data = vec![0; words_for(blocks.len(), bits)];
+        for (index, block_type) in blocks.iter().enumerate() {
+            let palette_index = palette.iter().position(|b| b == block_type).unwrap();
+            write_packed(&mut data, bits, index, palette_index as u64);
+        }
+        PaletteStorage::Paletted {
+            len: blocks.len(),
+            palette,
+            bits,
+            data,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            PaletteStorage::Uniform { len, .. } => *len,
+            PaletteStorage::Paletted { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_uniform(&self) -> bool {
+        matches!(self, PaletteStorage::Uniform { .. })
+    }
+
+    pub fn palette(&self) -> &[BlockType] {
+        match self {
+            PaletteStorage::Uniform { block_type, .. } => std::slice::from_ref(block_type),
+            PaletteStorage::Paletted { palette, .. } => palette,
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<BlockType> {
+        if index >= self.len() {
+            return None;
+        }
+        match self {
+            PaletteStorage::Uniform { block_type, .. } => Some(*block_type),
+            PaletteStorage::Paletted {
+                palette,
+                bits,
+                data,
+                ..
+            } => Some(palette[read_packed(data, *bits, index) as usize]),
+        }
+    }
+
+    // Returns false if the index is out of range
+    pub fn set(&mut self, index: usize, block_type: BlockType) -> bool {
+        if index >= self.len() {
+            return false;
+        }
+
+        if let PaletteStorage::Uniform {
+            len,
+            block_type: current,
+        } = *self
+        {
+            if current == block_type {
+                return true;
+            }
+            // Leaving the uniform fast path, every voxel starts at palette index 0
+            *self = PaletteStorage::Paletted {
+                len,
+                palette: vec![current],
+                bits: 1,
+                data: vec![0; words_for(len, 1)],
+            };
+        }
+
+        if let PaletteStorage::Paletted {
+            len,
+            palette,
+            bits,
+            data,
+        } = self
+        {
+            let palette_index = match palette.iter().position(|b| *b == block_type) {
+                Some(palette_index) => palette_index,
+                None => {
+                    palette.push(block_type);
+                    let needed_bits = bits_for(palette.len());
+                    if needed_bits > *bits {
+                        *data = repack(data, *bits, needed_bits, *len);
+                        *bits = needed_bits;
+                    }
+                    palette.len() - 1
+                }
+            };
+            write_packed(data, *bits, index, palette_index as u64);
+        }
+        true
+    }
+
+    // Whether every packed index points into the palette. Storage read from a file may be
+    // broken, get would panic on it.
+    pub fn indices_in_range(&self) -> bool {
+        match self {
+            PaletteStorage::Uniform { .. } => true,
+            PaletteStorage::Paletted {
+                len,
+                palette,
+                bits,
+                data,
+            } => (0..*len).all(|index| (read_packed(data, *bits, index) as usize) < palette.len()),
+        }
+    }
+
+    // Bytes used by this storage, including the heap allocations
+    pub fn memory_usage(&self) -> usize {
+        let heap = match self {
+            PaletteStorage::Uniform { .. } => 0,
+            PaletteStorage::Paletted { palette, data, .. } => {
+                palette.capacity() * size_of::<BlockType>() + data.capacity() * size_of::<u64>()
+            }
+        };
+        size_of::<Self>() + heap
+    }
+}
+
+fn bits_for(palette_len: usize) -> u32 {
+    let bits = usize::BITS - (palette_len.max(2) - 1).leading_zeros();
+    bits.max(1)
+}
+
+fn words_for(len: usize, bits: u32) -> usize {
+    let per_word = (64 / bits) as usize;
+    len.div_ceil(per_word)
+}
+
+fn read_packed(data: &[u64], bits: u32, index: usize) -> u64 {
+    let per_word = (64 / bits) as usize;
+    let shift = (index % per_word) as u32 * bits;
+    let mask = (1u64 << bits) - 1;
+    (data[index / per_word] >> shift) & mask
+}
+
+fn write_packed(data: &mut [u64], bits: u32, index: usize, value: u64) {
+    let per_word = (64 / bits) as usize;
+    let shift = (index % per_word) as u32 * bits;
+    let mask = (1u64 << bits) - 1;
+    let word = &mut data[index / per_word];
+    *word = (*word & !(mask << shift)) | ((value & mask) << shift);
+}
+
+fn repack(data: &[u64], old_bits: u32, new_bits: u32, len: usize) -> Vec<u64> {
+    let mut new_data = vec![0; words_for(len, new_bits)];
+    for index in 0..len {
+        write_packed(
+            &mut new_data,
+            new_bits,
+            index,
+            read_packed(data, old_bits, index),
+        );
+    }
+    new_data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bits_grow_with_the_palette() {
+        assert_eq!(bits_for(1), 1);
+        assert_eq!(bits_for(2), 1);
+        assert_eq!(bits_for(3), 2);
+        assert_eq!(bits_for(4), 2);
+        assert_eq!(bits_for(5), 3);
+        assert_eq!(bits_for(16), 4);
+        assert_eq!(bits_for(17), 5);
+        assert_eq!(bits_for(1 << 16), 16);
+    }
+
+    #[test]
+    fn packed_values_stay_within_their_word() {
+        // 5 bits fit 12 times in a word, the last 4 bits of each word stay unused
+        let bits = 5;
+        let len = 40;
+        let mut data = vec![0; words_for(len, bits)];
+        assert_eq!(data.len(), 4);
+        for index in 0..len {
+            write_packed(&mut data, bits, index, (index as u64 * 7) % 32);
+        }
+        for index in 0..len {
+            assert_eq!(read_packed(&data, bits, index), (index as u64 * 7) % 32);
+        }
+        for word in &data {
+            assert_eq!(word >> 60, 0);
+        }
+
+        // Overwriting a value leaves its neighbors on both sides of a word border alone
+        write_packed(&mut data, bits, 11, 31);
+        write_packed(&mut data, bits, 12, 0);
+        assert_eq!(read_packed(&data, bits, 10), 70 % 32);
+        assert_eq!(read_packed(&data, bits, 11), 31);
+        assert_eq!(read_packed(&data, bits, 12), 0);
+        assert_eq!(read_packed(&data, bits, 13), 91 % 32);
+    }
+
+    #[test]
+    fn repacking_keeps_every_value() {
+        let len = 100;
+        let mut data = vec![0; words_for(len, 2)];
+        for index in 0..len {
+            write_packed(&mut data, 2, index, index as u64 % 4);
+        }
+        let repacked = repack(&data, 2, 3, len);
+        assert_eq!(repacked.len(), words_for(len, 3));
+        for index in 0..len {
+            assert_eq!(read_packed(&repacked, 3, index), index as u64 % 4);
+        }
+    }
+
+    #[test]
+    fn setting_blocks_leaves_the_uniform_path_and_grows_the_palette() {
+        let stone = BlockType(1);
+        let mut storage = PaletteStorage::uniform(1000, stone);
+        // Writing the block it's already made of keeps it uniform
+        assert!(storage.set(5, stone));
+        assert!(storage.is_uniform());
+        assert!(!storage.set(1000, stone));
+
+        assert!(storage.set(5, BlockType::AIR));
+        assert!(!storage.is_uniform());
+        assert_eq!(storage.get(5), Some(BlockType::AIR));
+        assert_eq!(storage.get(4), Some(stone));
+        assert_eq!(storage.get(1000), None);
+
+        // Past two, then four block types the indices need more bits
+        for id in 2..=6 {
+            storage.set(id as usize * 100, BlockType(id));
+        }
+        match &storage {
+            PaletteStorage::Paletted { palette, bits, .. } => {
+                assert_eq!(palette.len(), 7);
+                assert_eq!(*bits, 3);
+            }
+            PaletteStorage::Uniform { .. } => panic!("storage went back to uniform"),
+        }
+        for index in 0..1000 {
+            let expected = match index {
+                5 => BlockType::AIR,
+                200 | 300 | 400 | 500 | 600 => BlockType(index as u16 / 100),
+                _ => stone,
+            };
+            assert_eq!(storage.get(index), Some(expected), "at {}", index);
+        }
+        assert!(storage.indices_in_range());
+    }
+
+    #[test]
+    fn from_blocks_picks_the_smallest_storage() {
+        let air = vec![BlockType::AIR; 64];
+        assert!(PaletteStorage::from_blocks(&air).is_uniform());
+
+        let mixed: Vec<BlockType> = (0..64).map(|index| BlockType(index % 3)).collect();
+        let storage = PaletteStorage::from_blocks(&mixed);
+        assert_eq!(storage.palette().len(), 3);
+        for (index, block_type) in mixed.iter().enumerate() {
+            assert_eq!(storage.get(index), Some(*block_type));
+        }
+    }
+}
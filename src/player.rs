@@ -0,0 +1,327 @@
+#![allow(dead_code)]
+use crate::{block::ChunkMap, camera::FlyCamera, registry::BlockRegistry};
+use bevy::prelude::*;
+
+// Size of the player's body in blocks, the camera sits at eye height above its feet
+pub const PLAYER_WIDTH: f32 = 0.6;
+pub const PLAYER_HEIGHT: f32 = 1.8;
+pub const EYE_HEIGHT: f32 = 1.62;
+
+const WALK_SPEED: f32 = 4.5;
+const GRAVITY: f32 = 28.0;
+// Enough to clear a single block
+const JUMP_SPEED: f32 = 8.5;
+const MAX_FALL_SPEED: f32 = 50.0;
+// Ledges up to this high are walked onto without jumping
+const STEP_HEIGHT: f32 = 1.0;
+
+// Water slows everything down and mostly cancels gravity
+const SWIM_SPEED: f32 = 3.0;
+const WATER_GRAVITY: f32 = 4.0;
+const WATER_DRAG: f32 = 4.0;
+
+// Keeps the body from counting as inside a voxel it only touches
+const EPSILON: f32 = 1e-4;
+
+// Axis aligned box in world space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    // The player's body standing with its feet at feet
+    pub fn body(feet: Vec3) -> Self {
+        let half = PLAYER_WIDTH / 2.0;
+        Self {
+            min: feet - Vec3::new(half, 0.0, half),
+            max: feet + Vec3::new(half, PLAYER_HEIGHT, half),
+        }
+    }
+
+    // The box filling a single voxel
+    pub fn voxel(world_pos: IVec3) -> Self {
+        Self {
+            min: world_pos.as_vec3(),
+            max: (world_pos + 1).as_vec3(),
+        }
+    }
+
+    // Whether the boxes overlap, boxes that only touch don't
+    pub fn intersects(self, other: Aabb) -> bool {
+        (self.min + EPSILON).cmplt(other.max).all() && (other.min + EPSILON).cmplt(self.max).all()
+    }
+
+    pub fn translated(self, offset: Vec3) -> Self {
+        Self {
+            min: self.min + offset,
+            max: self.max + offset,
+        }
+    }
+
+    // Voxels the box overlaps, not the ones it only touches
+    fn voxels(self) -> impl Iterator<Item = IVec3> {
+        let min = (self.min + EPSILON).floor().as_ivec3();
+        let max = (self.max - EPSILON).floor().as_ivec3();
+        (min.x..=max.x).flat_map(move |x| {
+            (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| IVec3::new(x, y, z)))
+        })
+    }
+}
+
+// How far the box can move along one axis before running into a solid voxel
+fn sweep_axis(aabb: Aabb, axis: usize, distance: f32, solid: &impl Fn(IVec3) -> bool) -> f32 {
+    if distance == 0.0 {
+        return 0.0;
+    }
+    // The voxels the box's cross section covers on the other two axes
+    let min = (aabb.min + EPSILON).floor().as_ivec3();
+    let max = (aabb.max - EPSILON).floor().as_ivec3();
+    let layer_is_solid = |layer: i32| {
+        let (mut min, mut max) = (min, max);
+        min[axis] = layer;
+        max[axis] = layer;
+        Aabb {
+            min: min.as_vec3(),
+            max: (max + 1).as_vec3(),
+        }
+        .voxels()
+        .any(solid)
+    };
+
+    if distance > 0.0 {
+        // Layers whose lower face lies between the box's leading face and where it ends up
+        let leading = aabb.max[axis];
+        let mut layer = (leading - EPSILON).ceil() as i32;
+        while (layer as f32) < leading + distance {
+            if layer_is_solid(layer) {
+                return (layer as f32 - leading).max(0.0);
+            }
+            layer += 1;
+        }
+    } else {
+        let leading = aabb.min[axis];
+        let mut layer = (leading + EPSILON).floor() as i32 - 1;
+        while (layer + 1) as f32 > leading + distance {
+            if layer_is_solid(layer) {
+                return ((layer + 1) as f32 - leading).min(0.0);
+            }
+            layer -= 1;
+        }
+    }
+    distance
+}
+
+// Moves the box one axis at a time, vertical first, stopping each axis at the first solid voxel
+// in the way. Returns how far it actually moved, an axis that came up short was blocked.
+pub fn sweep(aabb: Aabb, motion: Vec3, solid: &impl Fn(IVec3) -> bool) -> Vec3 {
+    let mut aabb = aabb;
+    let mut moved = Vec3::ZERO;
+    for axis in [1, 0, 2] {
+        moved[axis] = sweep_axis(aabb, axis, motion[axis], solid);
+        let mut offset = Vec3::ZERO;
+        offset[axis] = moved[axis];
+        aabb = aabb.translated(offset);
+    }
+    moved
+}
+
+// Moves the player's body, stepping up onto a single block when walking into one
+pub fn move_body(feet: Vec3, motion: Vec3, can_step: bool, solid: &impl Fn(IVec3) -> bool) -> Vec3 {
+    let body = Aabb::body(feet);
+    let moved = sweep(body, motion, solid);
+    let blocked = moved.x != motion.x || moved.z != motion.z;
+    if !can_step || !blocked {
+        return moved;
+    }
+
+    // Try the same move from a block higher, then settle back down onto whatever is there
+    let lifted = sweep(body, Vec3::Y * STEP_HEIGHT, solid);
+    let raised = body.translated(lifted);
+    let across = sweep(raised, Vec3::new(motion.x, 0.0, motion.z), solid);
+    let dropped = sweep(
+        raised.translated(across),
+        Vec3::new(0.0, motion.y.min(0.0) - lifted.y, 0.0),
+        solid,
+    );
+    if across.xz().length_squared() > moved.xz().length_squared() {
+        lifted + across + dropped
+    } else {
+        moved
+    }
+}
+
+// Walking state of the camera. Toggled with F, the camera flies through everything otherwise.
+#[derive(Component, Debug, Default)]
+pub struct Player {
+    pub velocity: Vec3,
+    pub on_ground: bool,
+    pub in_water: bool,
+}
+
+pub fn toggle_walking(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut query: Query<(&mut FlyCamera, &mut Player)>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::KeyF) {
+        return;
+    }
+    for (mut camera, mut player) in query.iter_mut() {
+        let flying = !camera.is_flying();
+        camera.set_flying(flying);
+        *player = Player::default();
+        println!("{}", if flying { "Flying" } else { "Walking" });
+    }
+}
+
+pub fn move_player(
+    keys: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+    chunk_map: Res<ChunkMap>,
+    registry: Res<BlockRegistry>,
+    mut query: Query<(&mut FlyCamera, &mut Player)>,
+) {
+    // Clamped so a hitch doesn't turn into one huge jump or fall
+    let delta_time = time.delta_seconds().min(0.05);
+    // Unloaded chunks are solid so the player can't fall out of the world before it streams in
+    let solid = |world_pos: IVec3| match chunk_map.get_block(world_pos) {
+        Some(block_type) => registry.is_solid(block_type),
+        None => true,
+    };
+    let water = registry.id("water");
+
+    for (mut camera, mut player) in query.iter_mut() {
+        if camera.is_flying() {
+            continue;
+        }
+        let feet = camera.get_position() - Vec3::Y * EYE_HEIGHT;
+        player.in_water = water.is_some()
+            && Aabb::body(feet)
+                .voxels()
+                .any(|world_pos| chunk_map.get_block(world_pos) == water);
+
+        // Walk along the ground in the direction the camera faces
+        let forward =
+            Vec3::new(camera.get_front().x, 0.0, camera.get_front().z).normalize_or_zero();
+        let right = Vec3::new(camera.get_right().x, 0.0, camera.get_right().z).normalize_or_zero();
+        let mut direction = Vec3::ZERO;
+        if keys.pressed(KeyCode::KeyW) {
+            direction += forward;
+        }
+        if keys.pressed(KeyCode::KeyS) {
+            direction -= forward;
+        }
+        if keys.pressed(KeyCode::KeyA) {
+            direction -= right;
+        }
+        if keys.pressed(KeyCode::KeyD) {
+            direction += right;
+        }
+        let speed = if player.in_water {
+            SWIM_SPEED
+        } else {
+            WALK_SPEED
+        };
+        let walk = direction.normalize_or_zero() * speed;
+        player.velocity.x = walk.x;
+        player.velocity.z = walk.z;
+
+        if player.in_water {
+            // Space swims up and shift dives, otherwise the player slowly sinks
+            player.velocity.y -= player.velocity.y * (WATER_DRAG * delta_time).min(1.0);
+            player.velocity.y -= WATER_GRAVITY * delta_time;
+            if keys.pressed(KeyCode::Space) {
+                player.velocity.y = SWIM_SPEED;
+            } else if keys.pressed(KeyCode::ShiftLeft) {
+                player.velocity.y = -SWIM_SPEED;
+            }
+        } else {
+            if player.on_ground && keys.pressed(KeyCode::Space) {
+                player.velocity.y = JUMP_SPEED;
+            }
+            player.velocity.y = (player.velocity.y - GRAVITY * delta_time).max(-MAX_FALL_SPEED);
+        }
+
+        let motion = player.velocity * delta_time;
+        let can_step = player.on_ground || player.in_water;
+        let moved = move_body(feet, motion, can_step, &solid);
+        // Landing or bumping a ceiling stops the fall or the jump
+        player.on_ground = motion.y < 0.0 && moved.y > motion.y;
+        if moved.y != motion.y {
+            player.velocity.y = 0.0;
+        }
+        camera.set_position(feet + moved + Vec3::Y * EYE_HEIGHT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A stone floor at y = 0 with the given extra solid voxels
+    fn world(blocks: &[IVec3]) -> impl Fn(IVec3) -> bool + '_ {
+        move |world_pos: IVec3| world_pos.y < 0 || blocks.contains(&world_pos)
+    }
+
+    #[test]
+    fn bodies_land_on_the_floor_and_stop_at_walls() {
+        let wall = [IVec3::new(2, 0, 0), IVec3::new(2, 1, 0)];
+        let solid = world(&wall);
+        let feet = Vec3::new(0.5, 3.0, 0.5);
+
+        // Falling far in one step still lands exactly on the floor
+        let moved = sweep(Aabb::body(feet), Vec3::new(0.0, -10.0, 0.0), &solid);
+        assert!((feet.y + moved.y).abs() < 1e-4);
+
+        // Walking into the wall stops with the body against it
+        let feet = Vec3::new(0.5, 0.0, 0.5);
+        let moved = sweep(Aabb::body(feet), Vec3::new(3.0, 0.0, 0.0), &solid);
+        assert!((feet.x + moved.x + PLAYER_WIDTH / 2.0 - 2.0).abs() < 1e-4);
+        // Sliding along it is still possible
+        let moved = sweep(Aabb::body(feet), Vec3::new(0.0, 0.0, 3.0), &solid);
+        assert_eq!(moved.z, 3.0);
+
+        // Touching a voxel isn't overlapping it
+        let feet = Vec3::new(2.0 - PLAYER_WIDTH / 2.0, 0.0, 0.5);
+        assert_eq!(
+            sweep(Aabb::body(feet), Vec3::new(0.0, 0.0, 1.0), &solid).z,
+            1.0
+        );
+    }
+
+    #[test]
+    fn bodies_overlap_the_voxels_they_stand_in() {
+        let body = Aabb::body(Vec3::new(0.5, 1.0, 0.5));
+        assert!(body.intersects(Aabb::voxel(IVec3::new(0, 1, 0))));
+        assert!(body.intersects(Aabb::voxel(IVec3::new(0, 2, 0))));
+        // The block under the feet and the ones beside the body only touch it
+        assert!(!body.intersects(Aabb::voxel(IVec3::new(0, 0, 0))));
+        assert!(!body.intersects(Aabb::voxel(IVec3::new(1, 1, 0))));
+        assert!(!body.intersects(Aabb::voxel(IVec3::new(0, 3, 0))));
+        // Standing across a voxel border reaches into both sides
+        let body = Aabb::body(Vec3::new(1.0, 1.0, 0.5));
+        assert!(body.intersects(Aabb::voxel(IVec3::new(1, 1, 0))));
+        assert!(body.intersects(Aabb::voxel(IVec3::new(0, 1, 0))));
+    }
+
+    #[test]
+    fn single_blocks_are_stepped_onto() {
+        let feet = Vec3::new(0.5, 0.0, 0.5);
+        let motion = Vec3::new(0.6, -0.01, 0.0);
+
+        // One block high: walk straight up onto it
+        let step = [IVec3::new(1, 0, 0)];
+        let moved = move_body(feet, motion, true, &world(&step));
+        assert!((moved.x - 0.6).abs() < 1e-4);
+        assert!((moved.y - 1.0).abs() < 1e-4);
+        // Not while in the air
+        let moved = move_body(feet, motion, false, &world(&step));
+        assert!(moved.x < 0.6 && moved.y <= 0.0);
+
+        // Two blocks high is a wall
+        let wall = [IVec3::new(1, 0, 0), IVec3::new(1, 1, 0)];
+        let moved = move_body(feet, motion, true, &world(&wall));
+        assert!(moved.x < 0.6 && moved.y <= 0.0);
+    }
+}
@@ -0,0 +1,180 @@
+#![allow(dead_code)]
+use crate::{
+    block::{BlockType, ChunkMap},
+    registry::BlockRegistry,
+};
+use bevy::prelude::*;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RaycastHit {
+    // World position of the block that was hit
+    pub block_pos: IVec3,
+    // Normal of the face the ray entered through, zero if the ray started inside the block
+    pub normal: IVec3,
+    // Distance along the ray to the entered face
+    pub distance: f32,
+    pub block_type: BlockType,
+}
+
+// Walks the voxel grid cell by cell along the ray (Amanatides & Woo DDA) and returns the first
+// solid block within max_distance. Non-solid blocks like water are passed through so blocks
+// under them can still be picked. Cells in unloaded chunks are treated as empty.
+pub fn raycast(
+    chunk_map: &ChunkMap,
+    registry: &BlockRegistry,
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+) -> Option<RaycastHit> {
+    let direction = direction.normalize_or_zero();
+    if direction == Vec3::ZERO {
+        return None;
+    }
+
+    let mut block_pos = origin.floor().as_ivec3();
+    let mut step = IVec3::ZERO;
+    let mut t_max = Vec3::INFINITY;
+    let mut t_delta = Vec3::INFINITY;
+    for axis in 0..3 {
+        if direction[axis] > 0.0 {
+            step[axis] = 1;
+            t_delta[axis] = 1.0 / direction[axis];
+            t_max[axis] = (block_pos[axis] as f32 + 1.0 - origin[axis]) * t_delta[axis];
+        } else if direction[axis] < 0.0 {
+            step[axis] = -1;
+            t_delta[axis] = -1.0 / direction[axis];
+            t_max[axis] = (origin[axis] - block_pos[axis] as f32) * t_delta[axis];
+        }
+    }
+
+    let mut normal = IVec3::ZERO;
+    let mut distance = 0.0;
+    loop {
+        if let Some(block_type) = chunk_map.get_block(block_pos) {
+            if registry.is_solid(block_type) {
+                return Some(RaycastHit {
+                    block_pos,
+                    normal,
+                    distance,
+                    block_type,
+                });
+            }
+        }
+
+        // Step into the neighbor cell whose boundary the ray crosses first
+        let axis = if t_max.x < t_max.y {
+            if t_max.x < t_max.z {
+                0
+            } else {
+                2
+            }
+        } else if t_max.y < t_max.z {
+            1
+        } else {
+            2
+        };
+        distance = t_max[axis];
+        if distance > max_distance {
+            return None;
+        }
+        block_pos[axis] += step[axis];
+        t_max[axis] += t_delta[axis];
+        normal = IVec3::ZERO;
+        normal[axis] = -step[axis];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Chunk;
+
+    // Air around the origin, reaching 32 blocks into negative coordinates, with the given
+    // blocks made of stone
+    fn world(registry: &BlockRegistry, blocks: &[IVec3]) -> ChunkMap {
+        let mut chunk_map = ChunkMap::with_seed(0);
+        for x in -1..=0 {
+            for y in -1..=0 {
+                for z in -1..=0 {
+                    chunk_map.insert_chunk(IVec3::new(x, y, z), Chunk::uniform(BlockType::AIR));
+                }
+            }
+        }
+        let stone = registry.id("stone").unwrap();
+        for world_pos in blocks {
+            chunk_map.set_block(*world_pos, stone);
+        }
+        chunk_map
+    }
+
+    #[test]
+    fn axis_aligned_rays_hit_the_facing_side() {
+        let registry = BlockRegistry::default();
+        let chunk_map = world(&registry, &[IVec3::new(5, 1, 1), IVec3::new(1, 6, 1)]);
+        let origin = Vec3::new(0.5, 1.5, 1.5);
+
+        // The direction doesn't need to be normalized
+        let hit = raycast(&chunk_map, &registry, origin, Vec3::new(2.0, 0.0, 0.0), 8.0).unwrap();
+        assert_eq!(hit.block_pos, IVec3::new(5, 1, 1));
+        assert_eq!(hit.normal, IVec3::NEG_X);
+        assert!((hit.distance - 4.5).abs() < 1e-4);
+        assert_eq!(hit.block_type, registry.id("stone").unwrap());
+
+        let origin = Vec3::new(1.5, 0.5, 1.5);
+        let hit = raycast(&chunk_map, &registry, origin, Vec3::Y, 8.0).unwrap();
+        assert_eq!(hit.block_pos, IVec3::new(1, 6, 1));
+        assert_eq!(hit.normal, IVec3::NEG_Y);
+        assert!((hit.distance - 5.5).abs() < 1e-4);
+
+        assert!(raycast(&chunk_map, &registry, origin, Vec3::NEG_X, 8.0).is_none());
+    }
+
+    #[test]
+    fn diagonal_rays_visit_every_cell_they_cross() {
+        let registry = BlockRegistry::default();
+        // The ray passes under (2, 2, 0) without touching it and climbs into (3, 2, 0) from below
+        let chunk_map = world(&registry, &[IVec3::new(2, 2, 0), IVec3::new(3, 2, 0)]);
+        let origin = Vec3::new(0.5, 0.5, 0.5);
+        let direction = Vec3::new(1.0, 0.5, 0.0);
+        let hit = raycast(&chunk_map, &registry, origin, direction, 8.0).unwrap();
+        assert_eq!(hit.block_pos, IVec3::new(3, 2, 0));
+        assert_eq!(hit.normal, IVec3::NEG_Y);
+        assert!((hit.distance - 3.0 * direction.length()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn rays_cross_into_negative_coordinates() {
+        let registry = BlockRegistry::default();
+        let chunk_map = world(&registry, &[IVec3::new(-1, -1, -6)]);
+        let origin = Vec3::new(-0.5, -0.5, -0.5);
+        let hit = raycast(&chunk_map, &registry, origin, Vec3::NEG_Z, 8.0).unwrap();
+        assert_eq!(hit.block_pos, IVec3::new(-1, -1, -6));
+        assert_eq!(hit.normal, IVec3::Z);
+        assert!((hit.distance - 4.5).abs() < 1e-4);
+
+        // Starting on the positive side still steps over the chunk border at zero
+        let origin = Vec3::new(-0.5, -0.5, 2.5);
+        let hit = raycast(&chunk_map, &registry, origin, Vec3::NEG_Z, 16.0).unwrap();
+        assert_eq!(hit.block_pos, IVec3::new(-1, -1, -6));
+    }
+
+    #[test]
+    fn blocks_past_max_distance_are_missed() {
+        let registry = BlockRegistry::default();
+        let chunk_map = world(&registry, &[IVec3::new(5, 1, 1)]);
+        let origin = Vec3::new(0.5, 1.5, 1.5);
+        assert!(raycast(&chunk_map, &registry, origin, Vec3::X, 4.4).is_none());
+        assert!(raycast(&chunk_map, &registry, origin, Vec3::X, 4.6).is_some());
+    }
+
+    #[test]
+    fn rays_starting_inside_a_block_hit_it_right_away() {
+        let registry = BlockRegistry::default();
+        let chunk_map = world(&registry, &[IVec3::new(5, 1, 1)]);
+        let origin = Vec3::new(5.5, 1.5, 1.5);
+        let hit = raycast(&chunk_map, &registry, origin, Vec3::X, 8.0).unwrap();
+        assert_eq!(hit.block_pos, IVec3::new(5, 1, 1));
+        assert_eq!(hit.normal, IVec3::ZERO);
+        assert_eq!(hit.distance, 0.0);
+    }
+}
@@ -0,0 +1,243 @@
+#![allow(dead_code)]
+use crate::block::BlockType;
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::{collections::HashMap, fs, path::Path, sync::Arc};
+
+pub const BLOCKS_PATH: &str = "assets/blocks.ron";
+
+// Copy of assets/blocks.ron built into the binary, used when the file can't be read
+const DEFAULT_BLOCKS: &str = include_str!("../assets/blocks.ron");
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockDefinition {
+    pub name: String,
+    #[serde(default = "default_color")]
+    pub color: [f32; 4],
+    // Texture used on every face, texture_top and texture_bottom override it on those faces
+    #[serde(default)]
+    pub texture: Option<String>,
+    #[serde(default)]
+    pub texture_top: Option<String>,
+    #[serde(default)]
+    pub texture_bottom: Option<String>,
+    #[serde(default = "default_true")]
+    pub solid: bool,
+    #[serde(default)]
+    pub transparent: bool,
+    #[serde(default)]
+    pub translucent: bool,
+    #[serde(default)]
+    pub light_emission: u8,
+    #[serde(default)]
+    pub hardness: f32,
+}
+
+fn default_color() -> [f32; 4] {
+    [1.0, 1.0, 1.0, 1.0]
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl BlockDefinition {
+    fn air() -> Self {
+        Self {
+            name: "air".to_string(),
+            color: [0.0, 0.0, 0.0, 0.0],
+            texture: None,
+            texture_top: None,
+            texture_bottom: None,
+            solid: false,
+            transparent: true,
+            translucent: false,
+            light_emission: 0,
+            hardness: 0.0,
+        }
+    }
+}
+
+// Properties of every block type, indexed by BlockType id.
+// Cloning is cheap so worker threads can take their own copy.
+#[derive(Resource, Debug, Clone)]
+pub struct BlockRegistry {
+    blocks: Arc<Vec<BlockDefinition>>,
+    by_name: Arc<HashMap<String, BlockType>>,
+    // Distinct texture names, texture i lives in atlas tile i + 1. Tile 0 is plain white and
+    // used by faces without a texture so they only show their color.
+    textures: Arc<Vec<String>>,
+    // Atlas tile of each block's top, side and bottom faces, indexed by block id
+    face_tiles: Arc<Vec<[u16; 3]>>,
+}
+
+impl Default for BlockRegistry {
+    fn default() -> Self {
+        Self::from_ron(DEFAULT_BLOCKS).expect("built-in block definitions are valid")
+    }
+}
+
+impl BlockRegistry {
+    pub fn from_ron(source: &str) -> Result<Self, String> {
+        let definitions: Vec<BlockDefinition> =
+            ron::from_str(source).map_err(|err| err.to_string())?;
+
+        let mut blocks = vec![BlockDefinition::air()];
+        let mut by_name = HashMap::new();
+        by_name.insert(blocks[0].name.clone(), BlockType::AIR);
+        for definition in definitions {
+            if by_name.contains_key(&definition.name) {
+                return Err(format!("block '{}' is defined twice", definition.name));
+            }
+            if definition.light_emission > 15 {
+                return Err(format!("block '{}' emits light above 15", definition.name));
+            }
+            let block_type = BlockType(blocks.len() as u16);
+            by_name.insert(definition.name.clone(), block_type);
+            blocks.push(definition);
+        }
+        if blocks.len() > u16::MAX as usize {
+            return Err("too many block types".to_string());
+        }
+
+        let mut textures: Vec<String> = Vec::new();
+        let mut tile_of = |name: Option<&String>| -> u16 {
+            let name = match name {
+                Some(name) => name,
+                None => return 0,
+            };
+            let index = match textures.iter().position(|texture| texture == name) {
+                Some(index) => index,
+                None => {
+                    textures.push(name.clone());
+                    textures.len() - 1
+                }
+            };
+            index as u16 + 1
+        };
+        let face_tiles = blocks
+            .iter()
+            .map(|definition| {
+                let side = definition.texture.as_ref();
+                [
+                    tile_of(definition.texture_top.as_ref().or(side)),
+                    tile_of(side),
+                    tile_of(definition.texture_bottom.as_ref().or(side)),
+                ]
+            })
+            .collect();
+
+        Ok(Self {
+            blocks: Arc::new(blocks),
+            by_name: Arc::new(by_name),
+            textures: Arc::new(textures),
+            face_tiles: Arc::new(face_tiles),
+        })
+    }
+
+    // Loads the block definitions from a file, falling back to the built-in ones
+    pub fn load_or_default(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let result = fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|source| Self::from_ron(&source));
+        match result {
+            Ok(registry) => {
+                println!(
+                    "Loaded {} block types from {}",
+                    registry.len(),
+                    path.display()
+                );
+                registry
+            }
+            Err(err) => {
+                println!(
+                    "Failed to load blocks from {}: {}, using built-in blocks",
+                    path.display(),
+                    err
+                );
+                Self::default()
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    // Unknown ids resolve to air
+    pub fn get(&self, block_type: BlockType) -> &BlockDefinition {
+        self.blocks
+            .get(block_type.0 as usize)
+            .unwrap_or(&self.blocks[0])
+    }
+
+    pub fn id(&self, name: &str) -> Option<BlockType> {
+        self.by_name.get(name).copied()
+    }
+
+    // Like id, but reports missing blocks and falls back to air
+    pub fn id_or_air(&self, name: &str) -> BlockType {
+        self.id(name).unwrap_or_else(|| {
+            println!("Block registry has no '{}' block, using air", name);
+            BlockType::AIR
+        })
+    }
+
+    pub fn name(&self, block_type: BlockType) -> &str {
+        &self.get(block_type).name
+    }
+
+    pub fn is_solid(&self, block_type: BlockType) -> bool {
+        self.get(block_type).solid
+    }
+
+    pub fn is_transparent(&self, block_type: BlockType) -> bool {
+        self.get(block_type).transparent
+    }
+
+    pub fn is_translucent(&self, block_type: BlockType) -> bool {
+        self.get(block_type).translucent
+    }
+
+    pub fn color(&self, block_type: BlockType) -> [f32; 4] {
+        self.get(block_type).color
+    }
+
+    pub fn light_emission(&self, block_type: BlockType) -> u8 {
+        self.get(block_type).light_emission
+    }
+
+    pub fn texture_names(&self) -> &[String] {
+        &self.textures
+    }
+
+    // Number of atlas tiles, including the white tile
+    pub fn tile_count(&self) -> usize {
+        self.textures.len() + 1
+    }
+
+    // Atlas tile of the block's face pointing along normal
+    pub fn face_tile(&self, block_type: BlockType, normal: IVec3) -> u16 {
+        let tiles = self
+            .face_tiles
+            .get(block_type.0 as usize)
+            .unwrap_or(&self.face_tiles[0]);
+        if normal.y > 0 {
+            tiles[0]
+        } else if normal.y < 0 {
+            tiles[2]
+        } else {
+            tiles[1]
+        }
+    }
+
+    // Every block type except air, in id order
+    pub fn placeable_blocks(&self) -> impl Iterator<Item = BlockType> + '_ {
+        (1..self.blocks.len()).map(|id| BlockType(id as u16))
+    }
+}
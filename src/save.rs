@@ -0,0 +1,605 @@
+#![allow(dead_code)]
+use crate::{
+    block::{BlockType, Chunk, ChunkMap, CHUNK_VOLUME},
+    palette::PaletteStorage,
+    registry::BlockRegistry,
+};
+use bevy::{app::AppExit, prelude::*};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+// Bumped whenever the on-disk layout changes, files with another version are not loaded
+pub const FORMAT_VERSION: u32 = 3;
+
+const LEVEL_MAGIC: &[u8; 4] = b"KYLV";
+const REGION_MAGIC: &[u8; 4] = b"KYRG";
+
+// Regions group REGION_SIZE^3 chunks into a single file
+pub const REGION_SIZE: i32 = 8;
+const REGION_VOLUME: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+// magic + version + seed
+const REGION_HEADER_SIZE: usize = 4 + 4 + 8;
+// One (offset, length) pair per chunk slot, offset 0 marks an empty slot
+const REGION_INDEX_SIZE: usize = REGION_VOLUME * 8;
+
+const STORAGE_UNIFORM: u8 = 0;
+const STORAGE_PALETTED: u8 = 1;
+
+// Region files layout:
+//   header: magic "KYRG", format version (u32), world seed (u64)
+//   index:  REGION_VOLUME x (payload offset u32, payload length u32)
+//   zlib compressed chunk payloads
+// All numbers are little endian.
+#[derive(Resource, Clone)]
+pub struct WorldSave {
+    pub dir: PathBuf,
+}
+
+impl Default for WorldSave {
+    fn default() -> Self {
+        Self::new("world")
+    }
+}
+
+impl WorldSave {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn level_path(&self) -> PathBuf {
+        self.dir.join("level.dat")
+    }
+
+    fn region_path(&self, region_pos: IVec3) -> PathBuf {
+        self.dir.join("region").join(format!(
+            "r.{}.{}.{}.kyr",
+            region_pos.x, region_pos.y, region_pos.z
+        ))
+    }
+
+    // Seed of the saved world, None if nothing was saved yet
+    pub fn load_seed(&self) -> io::Result<Option<u64>> {
+        let bytes = match fs::read(self.level_path()) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let mut reader = bytes.as_slice();
+        read_header(&mut reader, LEVEL_MAGIC).map(Some)
+    }
+
+    pub fn save_level(&self, seed: u64) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let mut bytes = Vec::new();
+        write_header(&mut bytes, LEVEL_MAGIC, seed);
+        fs::write(self.level_path(), bytes)
+    }
+
+    // Reads a single chunk, only touching the region's index and the chunk's own payload.
+    // Regions saved under another seed are rejected, their chunks wouldn't line up with the
+    // terrain generated around them.
+    pub fn load_chunk(
+        &self,
+        chunk_pos: IVec3,
+        seed: u64,
+        registry: &BlockRegistry,
+    ) -> io::Result<Option<Chunk>> {
+        let (region_pos, slot) = region_slot(chunk_pos);
+        let mut file = match File::open(self.region_path(region_pos)) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let mut header = [0; REGION_HEADER_SIZE];
+        file.read_exact(&mut header)?;
+        let region_seed = read_header(&mut header.as_slice(), REGION_MAGIC)?;
+        if region_seed != seed {
+            return Err(invalid_data(&format!(
+                "region saved with seed {} (expected {})",
+                region_seed, seed
+            )));
+        }
+
+        file.seek(SeekFrom::Start((REGION_HEADER_SIZE + slot * 8) as u64))?;
+        let mut entry = [0; 8];
+        file.read_exact(&mut entry)?;
+        let mut entry = entry.as_slice();
+        let offset = read_u32(&mut entry)?;
+        let length = read_u32(&mut entry)?;
+        if offset == 0 {
+            return Ok(None);
+        }
+
+        // Checked before allocating, a broken index could ask for gigabytes
+        if offset as u64 + length as u64 > file.metadata()?.len() {
+            return Err(invalid_data("region payload out of bounds"));
+        }
+        file.seek(SeekFrom::Start(offset as u64))?;
+        let mut payload = vec![0; length as usize];
+        file.read_exact(&mut payload)?;
+        decode_chunk(&payload, registry).map(Some)
+    }
+
+    // Writes the given chunks into their region files, keeping the other chunks already stored
+    // there. The level is written with the first region, so the seed is known after a crash.
+    pub fn save_chunks<'a>(
+        &self,
+        seed: u64,
+        registry: &BlockRegistry,
+        chunks: impl IntoIterator<Item = (IVec3, &'a Chunk)>,
+    ) -> io::Result<usize> {
+        let mut regions: HashMap<IVec3, Vec<(usize, Vec<u8>)>> = HashMap::new();
+        let mut count = 0;
+        for (chunk_pos, chunk) in chunks {
+            let (region_pos, slot) = region_slot(chunk_pos);
+            regions
+                .entry(region_pos)
+                .or_default()
+                .push((slot, encode_chunk(chunk, registry)?));
+            count += 1;
+        }
+
+        if !self.level_path().exists() {
+            self.save_level(seed)?;
+        }
+        fs::create_dir_all(self.dir.join("region"))?;
+        for (region_pos, payloads) in regions {
+            let path = self.region_path(region_pos);
+            let mut slots = read_region_payloads(&path, seed)?;
+            for (slot, payload) in payloads {
+                slots[slot] = Some(payload);
+            }
+            write_region(&path, seed, &slots)?;
+        }
+        Ok(count)
+    }
+}
+
+// Region containing the chunk and the chunk's slot inside it
+fn region_slot(chunk_pos: IVec3) -> (IVec3, usize) {
+    let region_pos = IVec3::new(
+        chunk_pos.x.div_euclid(REGION_SIZE),
+        chunk_pos.y.div_euclid(REGION_SIZE),
+        chunk_pos.z.div_euclid(REGION_SIZE),
+    );
+    let local = chunk_pos - region_pos * REGION_SIZE;
+    let slot = (local.x * REGION_SIZE * REGION_SIZE + local.y * REGION_SIZE + local.z) as usize;
+    (region_pos, slot)
+}
+
+// Chunks stored in a region file. Regions saved under another seed belong to another world
+// and are rejected, so saving never overwrites them.
+fn read_region_payloads(path: &PathBuf, seed: u64) -> io::Result<Vec<Option<Vec<u8>>>> {
+    let mut slots = vec![None; REGION_VOLUME];
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(slots),
+        Err(err) => return Err(err),
+    };
+
+    let mut reader = bytes.as_slice();
+    let region_seed = read_header(&mut reader, REGION_MAGIC)?;
+    if region_seed != seed {
+        return Err(invalid_data(&format!(
+            "region saved with seed {} (expected {})",
+            region_seed, seed
+        )));
+    }
+    for slot in slots.iter_mut() {
+        let offset = read_u32(&mut reader)? as usize;
+        let length = read_u32(&mut reader)? as usize;
+        if offset == 0 {
+            continue;
+        }
+        let payload = bytes
+            .get(offset..offset + length)
+            .ok_or_else(|| invalid_data("region payload out of bounds"))?;
+        *slot = Some(payload.to_vec());
+    }
+    Ok(slots)
+}
+
+fn write_region(path: &PathBuf, seed: u64, slots: &[Option<Vec<u8>>]) -> io::Result<()> {
+    let mut bytes = Vec::new();
+    write_header(&mut bytes, REGION_MAGIC, seed);
+
+    let mut offset = REGION_HEADER_SIZE + REGION_INDEX_SIZE;
+    for slot in slots {
+        match slot {
+            Some(payload) => {
+                bytes.extend((offset as u32).to_le_bytes());
+                bytes.extend((payload.len() as u32).to_le_bytes());
+                offset += payload.len();
+            }
+            None => bytes.extend([0; 8]),
+        }
+    }
+    for payload in slots.iter().flatten() {
+        bytes.extend(payload);
+    }
+
+    // Write next to the region and swap it in, so a crash mid-save doesn't corrupt the old file
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, bytes)?;
+    fs::rename(tmp_path, path)
+}
+
+// Chunk payload before compression:
+//   uniform:  0u8, block name, voxel count (u32)
+//   paletted: 1u8, voxel count (u32), palette length (u16), block names,
+//             bits per index (u8), word count (u32), packed words (u64 each)
+// Block names are a length (u16) followed by utf-8 bytes. Storing names instead of ids keeps
+// saves valid when blocks.ron is reordered or extended.
+pub fn encode_chunk(chunk: &Chunk, registry: &BlockRegistry) -> io::Result<Vec<u8>> {
+    let mut raw = Vec::new();
+    match chunk.storage() {
+        PaletteStorage::Uniform { len, block_type } => {
+            raw.push(STORAGE_UNIFORM);
+            write_name(&mut raw, registry.name(*block_type));
+            raw.extend((*len as u32).to_le_bytes());
+        }
+        PaletteStorage::Paletted {
+            len,
+            palette,
+            bits,
+            data,
+        } => {
+            raw.push(STORAGE_PALETTED);
+            raw.extend((*len as u32).to_le_bytes());
+            raw.extend((palette.len() as u16).to_le_bytes());
+            for block_type in palette {
+                write_name(&mut raw, registry.name(*block_type));
+            }
+            raw.push(*bits as u8);
+            raw.extend((data.len() as u32).to_le_bytes());
+            for word in data {
+                raw.extend(word.to_le_bytes());
+            }
+        }
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&raw)?;
+    encoder.finish()
+}
+
+pub fn decode_chunk(payload: &[u8], registry: &BlockRegistry) -> io::Result<Chunk> {
+    let mut raw = Vec::new();
+    ZlibDecoder::new(payload).read_to_end(&mut raw)?;
+    let mut reader = raw.as_slice();
+
+    let storage = match read_u8(&mut reader)? {
+        STORAGE_UNIFORM => {
+            let block_type = read_block(&mut reader, registry)?;
+            let len = read_u32(&mut reader)? as usize;
+            PaletteStorage::Uniform { len, block_type }
+        }
+        STORAGE_PALETTED => {
+            let len = read_u32(&mut reader)? as usize;
+            let palette_len = read_u16(&mut reader)? as usize;
+            let palette = (0..palette_len)
+                .map(|_| read_block(&mut reader, registry))
+                .collect::<io::Result<Vec<BlockType>>>()?;
+            let bits = read_u8(&mut reader)? as u32;
+            let word_count = read_u32(&mut reader)? as usize;
+            let data = (0..word_count)
+                .map(|_| read_u64(&mut reader))
+                .collect::<io::Result<Vec<u64>>>()?;
+
+            if palette.is_empty() || bits == 0 || bits > 16 || palette_len > 1 << bits {
+                return Err(invalid_data("bad chunk palette"));
+            }
+            let per_word = (64 / bits) as usize;
+            if word_count != len.div_ceil(per_word) {
+                return Err(invalid_data("bad chunk data length"));
+            }
+            let storage = PaletteStorage::Paletted {
+                len,
+                palette,
+                bits,
+                data,
+            };
+            if !storage.indices_in_range() {
+                return Err(invalid_data("chunk palette index out of range"));
+            }
+            storage
+        }
+        _ => return Err(invalid_data("unknown chunk storage")),
+    };
+    if storage.len() != CHUNK_VOLUME {
+        return Err(invalid_data("chunk size mismatch"));
+    }
+    Ok(Chunk::from_storage(storage))
+}
+
+fn write_header(bytes: &mut Vec<u8>, magic: &[u8; 4], seed: u64) {
+    bytes.extend(magic);
+    bytes.extend(FORMAT_VERSION.to_le_bytes());
+    bytes.extend(seed.to_le_bytes());
+}
+
+// Checks magic and version and returns the stored seed
+fn read_header(reader: &mut &[u8], magic: &[u8; 4]) -> io::Result<u64> {
+    let mut file_magic = [0; 4];
+    reader.read_exact(&mut file_magic)?;
+    if &file_magic != magic {
+        return Err(invalid_data("bad magic"));
+    }
+    let version = read_u32(reader)?;
+    if version != FORMAT_VERSION {
+        return Err(invalid_data(&format!(
+            "unsupported format version {} (expected {})",
+            version, FORMAT_VERSION
+        )));
+    }
+    read_u64(reader)
+}
+
+fn write_name(bytes: &mut Vec<u8>, name: &str) {
+    bytes.extend((name.len() as u16).to_le_bytes());
+    bytes.extend(name.as_bytes());
+}
+
+// Blocks that were removed from the registry since the chunk was saved load as air
+fn read_block(reader: &mut &[u8], registry: &BlockRegistry) -> io::Result<BlockType> {
+    let len = read_u16(reader)? as usize;
+    let mut name = vec![0; len];
+    reader.read_exact(&mut name)?;
+    let name = String::from_utf8(name).map_err(|_| invalid_data("bad block name"))?;
+    Ok(registry.id_or_air(&name))
+}
+
+fn read_u8(reader: &mut &[u8]) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16(reader: &mut &[u8]) -> io::Result<u16> {
+    let mut buf = [0; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32(reader: &mut &[u8]) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut &[u8]) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Saves the given loaded chunks, clears their modified flag and keeps decorations out of them
+pub fn save_loaded_chunks(
+    save: &WorldSave,
+    chunk_map: &mut ChunkMap,
+    registry: &BlockRegistry,
+    chunk_positions: &[IVec3],
+) {
+    let chunks = chunk_positions.iter().filter_map(|chunk_pos| {
+        chunk_map
+            .get_chunk(*chunk_pos)
+            .map(|chunk| (*chunk_pos, chunk))
+    });
+    match save.save_chunks(chunk_map.seed, registry, chunks) {
+        Ok(_) => {
+            for chunk_pos in chunk_positions {
+                chunk_map.modified.remove(chunk_pos);
+                if chunk_map.map.contains_key(chunk_pos) {
+                    chunk_map.saved.insert(*chunk_pos);
+                }
+            }
+        }
+        Err(err) => println!("Failed to save chunks: {}", err),
+    }
+}
+
+fn save_world(save: &WorldSave, chunk_map: &mut ChunkMap, registry: &BlockRegistry) {
+    if let Err(err) = save.save_level(chunk_map.seed) {
+        println!("Failed to save world: {}", err);
+        return;
+    }
+    let chunk_positions: Vec<IVec3> = chunk_map.map.keys().copied().collect();
+    save_loaded_chunks(save, chunk_map, registry, &chunk_positions);
+    println!(
+        "Saved {} chunks to {}",
+        chunk_positions.len(),
+        save.dir.display()
+    );
+}
+
+pub fn save_world_system(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    save: Res<WorldSave>,
+    registry: Res<BlockRegistry>,
+    mut chunk_map: ResMut<ChunkMap>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F5) {
+        save_world(&save, &mut chunk_map, &registry);
+    }
+}
+
+pub fn save_on_exit(
+    mut exit_events: EventReader<AppExit>,
+    save: Res<WorldSave>,
+    registry: Res<BlockRegistry>,
+    mut chunk_map: ResMut<ChunkMap>,
+) {
+    if exit_events.read().next().is_some() {
+        save_world(&save, &mut chunk_map, &registry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An empty save in the temp directory, cleared from earlier runs
+    fn test_save(name: &str) -> WorldSave {
+        let dir = std::env::temp_dir().join(format!("kyuubic-test-{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        WorldSave::new(dir)
+    }
+
+    fn blocks(chunk: &Chunk) -> Vec<BlockType> {
+        (0..CHUNK_VOLUME)
+            .map(|index| chunk.get_voxel_by_index(index).unwrap())
+            .collect()
+    }
+
+    // Stone below y = 8, a layer of dirt, grass on top and air above
+    fn layered_chunk(registry: &BlockRegistry) -> Chunk {
+        let voxels: Vec<BlockType> = crate::coords::local_positions()
+            .map(|local_pos| match local_pos.y {
+                y if y < 8 => registry.id("stone").unwrap(),
+                8 => registry.id("dirt").unwrap(),
+                9 => registry.id("grass").unwrap(),
+                _ => BlockType::AIR,
+            })
+            .collect();
+        Chunk::from_blocks(&voxels)
+    }
+
+    #[test]
+    fn chunks_round_trip_through_encoding() {
+        let registry = BlockRegistry::default();
+        let uniform = Chunk::uniform(registry.id("stone").unwrap());
+        let decoded = decode_chunk(&encode_chunk(&uniform, &registry).unwrap(), &registry).unwrap();
+        assert!(decoded.is_uniform());
+        assert_eq!(blocks(&decoded), blocks(&uniform));
+
+        let layered = layered_chunk(&registry);
+        let decoded = decode_chunk(&encode_chunk(&layered, &registry).unwrap(), &registry).unwrap();
+        assert!(!decoded.is_uniform());
+        assert_eq!(decoded.palette_len(), 4);
+        assert_eq!(blocks(&decoded), blocks(&layered));
+    }
+
+    #[test]
+    fn broken_palette_indices_are_rejected() {
+        let registry = BlockRegistry::default();
+        // Three palette entries in two bits leave index 3 pointing past the palette
+        let storage = PaletteStorage::Paletted {
+            len: CHUNK_VOLUME,
+            palette: vec![BlockType::AIR, BlockType(1), BlockType(2)],
+            bits: 2,
+            data: vec![u64::MAX; CHUNK_VOLUME / 32],
+        };
+        let payload = encode_chunk(&Chunk::from_storage(storage), &registry).unwrap();
+        let err = decode_chunk(&payload, &registry).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn chunks_round_trip_through_region_files() {
+        let registry = BlockRegistry::default();
+        let save = test_save("region-round-trip");
+        let stone = Chunk::uniform(registry.id("stone").unwrap());
+        let layered = layered_chunk(&registry);
+
+        // The first two share region (-1, -1, -1), the third is in region (0, 0, 0)
+        let first = [
+            (IVec3::new(-1, -1, -1), &stone),
+            (IVec3::new(-8, -3, -5), &layered),
+            (IVec3::new(0, 0, 0), &layered),
+        ];
+        assert_eq!(save.save_chunks(7, &registry, first).unwrap(), 3);
+        // Saving another chunk into the region keeps the ones already there
+        let second = [(IVec3::new(-2, -1, -1), &layered)];
+        save.save_chunks(7, &registry, second).unwrap();
+
+        for (chunk_pos, chunk) in first.into_iter().chain(second) {
+            let loaded = save.load_chunk(chunk_pos, 7, &registry).unwrap().unwrap();
+            assert_eq!(blocks(&loaded), blocks(chunk), "chunk {}", chunk_pos);
+        }
+        assert!(save
+            .load_chunk(IVec3::new(-3, -1, -1), 7, &registry)
+            .unwrap()
+            .is_none());
+        assert!(save
+            .load_chunk(IVec3::new(20, 0, 0), 7, &registry)
+            .unwrap()
+            .is_none());
+        // The level is written with the first region
+        assert_eq!(save.load_seed().unwrap(), Some(7));
+        let _ = fs::remove_dir_all(&save.dir);
+    }
+
+    #[test]
+    fn regions_from_another_seed_or_version_are_rejected() {
+        let registry = BlockRegistry::default();
+        let save = test_save("region-rejected");
+        let chunk_pos = IVec3::new(1, 2, 3);
+        save.save_chunks(7, &registry, [(chunk_pos, &layered_chunk(&registry))])
+            .unwrap();
+        assert!(save.load_chunk(chunk_pos, 8, &registry).is_err());
+        // Saving under another seed leaves the region alone
+        let other_pos = chunk_pos + IVec3::X;
+        let stone = Chunk::uniform(registry.id("stone").unwrap());
+        assert!(save
+            .save_chunks(8, &registry, [(other_pos, &stone)])
+            .is_err());
+        assert!(save.load_chunk(chunk_pos, 7, &registry).unwrap().is_some());
+        assert!(save.load_chunk(other_pos, 7, &registry).unwrap().is_none());
+
+        let path = save.region_path(region_slot(chunk_pos).0);
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        fs::write(&path, bytes).unwrap();
+        let err = save.load_chunk(chunk_pos, 7, &registry).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let _ = fs::remove_dir_all(&save.dir);
+    }
+
+    #[test]
+    fn payloads_past_the_end_of_the_region_are_rejected() {
+        let registry = BlockRegistry::default();
+        let save = test_save("region-truncated");
+        let chunk_pos = IVec3::new(1, 2, 3);
+        save.save_chunks(7, &registry, [(chunk_pos, &layered_chunk(&registry))])
+            .unwrap();
+
+        // An index entry claiming a payload of almost 4 GiB
+        let (region_pos, slot) = region_slot(chunk_pos);
+        let path = save.region_path(region_pos);
+        let mut bytes = fs::read(&path).unwrap();
+        let length_at = REGION_HEADER_SIZE + slot * 8 + 4;
+        bytes[length_at..length_at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, bytes).unwrap();
+        let err = save.load_chunk(chunk_pos, 7, &registry).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let _ = fs::remove_dir_all(&save.dir);
+    }
+
+    #[test]
+    fn removed_blocks_load_as_air() {
+        let registry = BlockRegistry::default();
+        let payload = encode_chunk(&layered_chunk(&registry), &registry).unwrap();
+        // Only stone is left, and it moved to another id
+        let trimmed = BlockRegistry::from_ron(r#"[(name: "lamp"), (name: "stone")]"#).unwrap();
+        let decoded = decode_chunk(&payload, &trimmed).unwrap();
+        let stone = trimmed.id("stone").unwrap();
+        for (local_pos, block_type) in crate::coords::local_positions().zip(blocks(&decoded)) {
+            let expected = if local_pos.y < 8 {
+                stone
+            } else {
+                BlockType::AIR
+            };
+            assert_eq!(block_type, expected, "at {}", local_pos);
+        }
+    }
+}
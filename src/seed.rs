@@ -0,0 +1,148 @@
+#![allow(dead_code)]
+use crate::settings::TerrainSettings;
+use rand::Rng;
+
+// Turns a seed typed by the user into the world seed. Numbers are used as they are, anything
+// else is hashed, so "island" gives the same world every time.
+pub fn parse_seed(value: &str) -> u64 {
+    let value = value.trim();
+    value.parse().unwrap_or_else(|_| fnv1a(value.as_bytes()))
+}
+
+// 64 bit FNV-1a, small and stable across platforms and Rust versions unlike DefaultHasher
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+// Value of --seed, given as "--seed value" or "--seed=value"
+pub fn seed_argument(args: impl IntoIterator<Item = String>) -> Option<String> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--seed" {
+            return args.next();
+        }
+        if let Some(value) = arg.strip_prefix("--seed=") {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+// Seed for a new world: the command line first, then the terrain settings, otherwise random.
+// Returns where it came from too, for the log.
+pub fn select_seed(
+    args: impl IntoIterator<Item = String>,
+    settings: &TerrainSettings,
+) -> (u64, &'static str) {
+    if let Some(value) = seed_argument(args) {
+        return (parse_seed(&value), "the command line");
+    }
+    if let Some(value) = &settings.seed {
+        return (parse_seed(value), "the terrain settings");
+    }
+    (rand::thread_rng().gen(), "random")
+}
+
+// Seed of the world to play. A saved world keeps the seed its chunks were generated from,
+// anything else gets a new one from select_seed.
+pub fn world_seed(
+    args: impl IntoIterator<Item = String>,
+    settings: &TerrainSettings,
+    saved_seed: Option<u64>,
+) -> (u64, &'static str) {
+    match saved_seed {
+        Some(seed) => (seed, "the saved world"),
+        None => select_seed(args, settings),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{coords, registry::BlockRegistry, save, terrain::TerrainGenerator};
+    use bevy::prelude::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn seeds_parse_from_numbers_and_text() {
+        assert_eq!(parse_seed("12345"), 12345);
+        assert_eq!(parse_seed(" 7 "), 7);
+        assert_eq!(parse_seed("island"), parse_seed("island"));
+        assert_ne!(parse_seed("island"), parse_seed("Island"));
+        // Reference values of FNV-1a
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn command_line_seed_wins_over_settings() {
+        let settings = TerrainSettings {
+            seed: Some("from settings".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            seed_argument(args(&["game", "--seed", "42"])),
+            Some("42".to_string())
+        );
+        assert_eq!(
+            seed_argument(args(&["game", "--seed=abc"])),
+            Some("abc".to_string())
+        );
+        assert_eq!(seed_argument(args(&["game", "--seed"])), None);
+        assert_eq!(
+            select_seed(args(&["game", "--seed", "42"]), &settings).0,
+            42
+        );
+        assert_eq!(
+            select_seed(args(&["game"]), &settings).0,
+            parse_seed("from settings")
+        );
+    }
+
+    #[test]
+    fn saved_worlds_keep_their_seed() {
+        let settings = TerrainSettings::default();
+        assert_eq!(
+            world_seed(args(&["game", "--seed", "42"]), &settings, Some(7)),
+            (7, "the saved world")
+        );
+        assert_eq!(
+            world_seed(args(&["game", "--seed", "42"]), &settings, None),
+            (42, "the command line")
+        );
+    }
+
+    #[test]
+    fn same_seed_generates_identical_chunks() {
+        let registry = BlockRegistry::default();
+        let settings = TerrainSettings::default();
+        let seed = parse_seed("regression");
+        let encode_all = |seed: u64| -> Vec<Vec<u8>> {
+            // A fresh generator every time, so nothing carries over between runs
+            let generator = TerrainGenerator::new(seed, &registry, &settings);
+            [
+                IVec3::new(0, 0, 0),
+                IVec3::new(-3, 1, 2),
+                IVec3::new(5, -2, -7),
+                coords::world_to_chunk(IVec3::new(1000, 40, -1000)),
+            ]
+            .into_iter()
+            .map(|chunk_pos| {
+                save::encode_chunk(&generator.generate_chunk(chunk_pos), &registry).unwrap()
+            })
+            .collect()
+        };
+
+        let first = encode_all(seed);
+        assert_eq!(encode_all(seed), first);
+        assert_ne!(encode_all(seed + 1), first);
+    }
+}
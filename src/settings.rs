@@ -0,0 +1,199 @@
+#![allow(dead_code)]
+use bevy::prelude::*;
+use bracket_noise::prelude::NoiseType;
+use serde::Deserialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+pub const TERRAIN_SETTINGS_PATH: &str = "assets/terrain.ron";
+
+// Noise used for the terrain's height detail, the octaves are layered on top of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum DetailNoise {
+    Simplex,
+    Perlin,
+    Value,
+    Cubic,
+}
+
+impl DetailNoise {
+    pub fn noise_type(self) -> NoiseType {
+        match self {
+            DetailNoise::Simplex => NoiseType::Simplex,
+            DetailNoise::Perlin => NoiseType::Perlin,
+            DetailNoise::Value => NoiseType::Value,
+            DetailNoise::Cubic => NoiseType::Cubic,
+        }
+    }
+}
+
+// Tunables of the terrain generator. Anything left out of the file keeps its default.
+#[derive(Resource, Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TerrainSettings {
+    // Seed of new worlds, a number or any text, unless --seed is given. Only read at startup.
+    pub seed: Option<String>,
+    pub noise_type: DetailNoise,
+    // Layers of noise summed for the height detail, each one finer and weaker than the last
+    pub octaves: u32,
+    // Frequency multiplier from one octave to the next
+    pub lacunarity: f32,
+    // Amplitude multiplier from one octave to the next
+    pub gain: f32,
+    // Size of the largest terrain features in blocks
+    pub scale: f32,
+    // Water fills everything below this height, biome heights are relative to it
+    pub sea_level: i32,
+    // Ground at or above this height is covered in snow
+    pub snow_line: i32,
+    // Blocks of dirt below the grass and snow
+    pub dirt_depth: i32,
+}
+
+impl Default for TerrainSettings {
+    fn default() -> Self {
+        Self {
+            seed: None,
+            noise_type: DetailNoise::Simplex,
+            octaves: 4,
+            lacunarity: 2.0,
+            gain: 0.5,
+            scale: 200.0,
+            sea_level: 30,
+            snow_line: 110,
+            dirt_depth: 4,
+        }
+    }
+}
+
+impl TerrainSettings {
+    pub fn from_ron(source: &str) -> Result<Self, String> {
+        let settings: TerrainSettings = ron::from_str(source).map_err(|err| err.to_string())?;
+        if !(1..=8).contains(&settings.octaves) {
+            return Err(format!("octaves must be 1 to 8, got {}", settings.octaves));
+        }
+        if settings.scale <= 0.0 || settings.lacunarity <= 0.0 {
+            return Err("scale and lacunarity must be above 0".to_string());
+        }
+        if settings.dirt_depth < 0 {
+            return Err("dirt_depth can't be negative".to_string());
+        }
+        Ok(settings)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        Self::from_ron(&source)
+    }
+
+    // Loads the settings from a file, falling back to the defaults
+    pub fn load_or_default(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        match Self::load(path) {
+            Ok(settings) => {
+                println!("Loaded terrain settings from {}", path.display());
+                settings
+            }
+            Err(err) => {
+                println!(
+                    "Failed to load terrain settings from {}: {}, using defaults",
+                    path.display(),
+                    err
+                );
+                Self::default()
+            }
+        }
+    }
+}
+
+// Polls the settings file for changes so it can be tuned while the game runs
+#[derive(Resource, Debug)]
+pub struct TerrainSettingsWatcher {
+    pub path: PathBuf,
+    pub modified: Option<SystemTime>,
+    pub timer: Timer,
+}
+
+impl TerrainSettingsWatcher {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self {
+            modified: modified_time(&path),
+            path,
+            timer: Timer::from_seconds(1.0, TimerMode::Repeating),
+        }
+    }
+}
+
+impl Default for TerrainSettingsWatcher {
+    fn default() -> Self {
+        Self::new(TERRAIN_SETTINGS_PATH)
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// Reloads the settings when the file's modification time changes. A broken file keeps the
+// current settings, so a half saved edit doesn't wipe the world.
+pub fn reload_terrain_settings(
+    time: Res<Time>,
+    mut watcher: ResMut<TerrainSettingsWatcher>,
+    mut settings: ResMut<TerrainSettings>,
+) {
+    if !watcher.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let modified = modified_time(&watcher.path);
+    if modified == watcher.modified {
+        return;
+    }
+    watcher.modified = modified;
+
+    match TerrainSettings::load(&watcher.path) {
+        Ok(new_settings) => {
+            if new_settings != *settings {
+                println!("Reloaded terrain settings from {}", watcher.path.display());
+                *settings = new_settings;
+            }
+        }
+        Err(err) => println!(
+            "Failed to reload terrain settings from {}: {}, keeping the current ones",
+            watcher.path.display(),
+            err
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_file_matches_the_defaults() {
+        let source = include_str!("../assets/terrain.ron");
+        assert_eq!(
+            TerrainSettings::from_ron(source).unwrap(),
+            TerrainSettings::default()
+        );
+    }
+
+    #[test]
+    fn missing_fields_keep_their_defaults() {
+        let settings = TerrainSettings::from_ron("(octaves: 6, noise_type: Perlin)").unwrap();
+        assert_eq!(settings.octaves, 6);
+        assert_eq!(settings.noise_type, DetailNoise::Perlin);
+        assert_eq!(settings.sea_level, TerrainSettings::default().sea_level);
+        let settings =
+            TerrainSettings::from_ron("#![enable(implicit_some)]\n(seed: \"island\")").unwrap();
+        assert_eq!(settings.seed.as_deref(), Some("island"));
+        assert!(TerrainSettings::from_ron("(octaves: 0)").is_err());
+        assert!(TerrainSettings::from_ron("(scale: -1.0)").is_err());
+    }
+}
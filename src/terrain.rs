@@ -0,0 +1,821 @@
+#![allow(dead_code)]
+use crate::{
+    biome::{BiomeSet, Caves, Climate, Decoration},
+    block::{BlockType, Chunk, CHUNK_HEIGHT, CHUNK_SIZE, CHUNK_VOLUME},
+    coords,
+    light::{self, MAX_LIGHT},
+    ore::OreSet,
+    registry::BlockRegistry,
+    settings::TerrainSettings,
+};
+use bevy::prelude::*;
+use bracket_noise::prelude::*;
+
+// World height reached by the highest peaks
+pub const TERRAIN_HEIGHT: f32 = 192.0;
+// Size of the climate features in blocks, so a biome spans a few hundred blocks
+const CLIMATE_SCALE: f32 = 512.0;
+const CONTINENT_SCALE: f32 = 1024.0;
+// Columns sampled per side for one chunk: the chunk plus a border column from each neighbor, so
+// generation can look at the surface around a column without its chunk being loaded
+const PADDED_SIZE: i32 = CHUNK_SIZE + 2;
+// Ground kept between a cave below sea level and the water around it
+const CAVE_CRUST: i32 = 3;
+
+// Registry ids of the blocks the generator places, looked up by name once
+#[derive(Debug, Clone, Copy)]
+pub struct TerrainBlocks {
+    pub stone: BlockType,
+    pub dirt: BlockType,
+    pub grass: BlockType,
+    pub snow: BlockType,
+    pub water: BlockType,
+    pub sand: BlockType,
+    pub sandstone: BlockType,
+    pub gravel: BlockType,
+    pub cactus: BlockType,
+    pub ice: BlockType,
+    pub coal_ore: BlockType,
+    pub iron_ore: BlockType,
+    pub gold_ore: BlockType,
+    pub log: BlockType,
+    pub leaves: BlockType,
+}
+
+impl TerrainBlocks {
+    pub fn from_registry(registry: &BlockRegistry) -> Self {
+        Self {
+            stone: registry.id_or_air("stone"),
+            dirt: registry.id_or_air("dirt"),
+            grass: registry.id_or_air("grass"),
+            snow: registry.id_or_air("snow"),
+            water: registry.id_or_air("water"),
+            sand: registry.id_or_air("sand"),
+            sandstone: registry.id_or_air("sandstone"),
+            gravel: registry.id_or_air("gravel"),
+            cactus: registry.id_or_air("cactus"),
+            ice: registry.id_or_air("ice"),
+            coal_ore: registry.id_or_air("coal_ore"),
+            iron_ore: registry.id_or_air("iron_ore"),
+            gold_ore: registry.id_or_air("gold_ore"),
+            log: registry.id_or_air("log"),
+            leaves: registry.id_or_air("leaves"),
+        }
+    }
+}
+
+// Surface of one (x, z) column
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainColumn {
+    // World y of the topmost ground block
+    pub height: i32,
+    // Index into the generator's BiomeSet
+    pub biome: usize,
+}
+
+// Block placed by a decoration. Trees and structures can reach past the chunk that generated
+// them, so these are also handed to the ChunkMap to write into neighboring chunks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeatureWrite {
+    pub world_pos: IVec3,
+    pub block: BlockType,
+    // Block it may overwrite besides air, so trunks push through the leaves of other trees
+    pub replaces: BlockType,
+}
+
+impl FeatureWrite {
+    pub fn applies_to(&self, current: BlockType) -> bool {
+        current == BlockType::AIR || current == self.replaces
+    }
+}
+
+// A chunk and the decoration blocks that fell outside of it
+#[derive(Debug)]
+pub struct GeneratedChunk {
+    pub chunk: Chunk,
+    pub spill: Vec<FeatureWrite>,
+    // Read back from the save file rather than built from the seed
+    pub from_save: bool,
+}
+
+// Stable pseudo random number for a world position, the same on every run and in any chunk
+// order. Used wherever generation needs a dice roll.
+pub fn position_hash(seed: u64, pos: IVec3) -> u64 {
+    let mut hash = seed
+        ^ (pos.x as u32 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (pos.y as u32 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+        ^ (pos.z as u32 as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+    // splitmix64 finalizer
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    hash ^ (hash >> 31)
+}
+
+// position_hash mapped to 0..1
+pub fn position_chance(seed: u64, pos: IVec3) -> f32 {
+    (position_hash(seed, pos) >> 40) as f32 / (1u64 << 24) as f32
+}
+
+fn climate_noise(seed: u64, scale: f32) -> FastNoise {
+    let mut noise = FastNoise::seeded(seed);
+    noise.set_noise_type(NoiseType::SimplexFractal);
+    noise.set_fractal_octaves(3);
+    noise.set_frequency(1.0 / scale);
+    noise
+}
+
+// 3D noise fields carving the caves, built once per chunk
+struct CaveNoise {
+    cheese: FastNoise,
+    tunnel_a: FastNoise,
+    tunnel_b: FastNoise,
+}
+
+impl CaveNoise {
+    fn new(seed: u64) -> Self {
+        let mut cheese = FastNoise::seeded(seed.wrapping_add(4));
+        cheese.set_noise_type(NoiseType::SimplexFractal);
+        cheese.set_fractal_octaves(2);
+        cheese.set_frequency(1.0 / 48.0);
+        let mut tunnel_a = FastNoise::seeded(seed.wrapping_add(5));
+        tunnel_a.set_noise_type(NoiseType::Simplex);
+        tunnel_a.set_frequency(1.0 / 64.0);
+        let mut tunnel_b = FastNoise::seeded(seed.wrapping_add(6));
+        tunnel_b.set_noise_type(NoiseType::Simplex);
+        tunnel_b.set_frequency(1.0 / 64.0);
+        Self {
+            cheese,
+            tunnel_a,
+            tunnel_b,
+        }
+    }
+
+    fn carves(&self, caves: Caves, world_pos: IVec3) -> bool {
+        let pos = world_pos.as_vec3();
+        if caves.cheese_threshold < 1.0
+            && self.cheese.get_noise3d(pos.x, pos.y, pos.z) > caves.cheese_threshold
+        {
+            return true;
+        }
+        // Squashing y keeps tunnels closer to horizontal, so they can be walked
+        caves.tunnel_width > 0.0
+            && self.tunnel_a.get_noise3d(pos.x, pos.y * 2.0, pos.z).abs() < caves.tunnel_width
+            && self.tunnel_b.get_noise3d(pos.x, pos.y * 2.0, pos.z).abs() < caves.tunnel_width
+    }
+}
+
+fn padded_index(x: i32, z: i32) -> usize {
+    ((x + 1) * PADDED_SIZE + z + 1) as usize
+}
+
+// Builds chunks from a seed. Holds no reference to the ChunkMap so it can run on worker threads.
+#[derive(Debug, Clone)]
+pub struct TerrainGenerator {
+    pub seed: u64,
+    pub settings: TerrainSettings,
+    pub blocks: TerrainBlocks,
+    pub biomes: BiomeSet,
+    pub ores: OreSet,
+    // For lighting the chunks it builds
+    pub registry: BlockRegistry,
+}
+
+impl TerrainGenerator {
+    pub fn new(seed: u64, registry: &BlockRegistry, settings: &TerrainSettings) -> Self {
+        let blocks = TerrainBlocks::from_registry(registry);
+        Self {
+            seed,
+            settings: settings.clone(),
+            blocks,
+            biomes: BiomeSet::new(&blocks, settings),
+            ores: OreSet::new(&blocks),
+            registry: registry.clone(),
+        }
+    }
+
+    // Octaves of the detail noise summed from coarse to fine, scaled back into -1..1
+    fn detail_noise(&self, noise: &FastNoise, x: f32, z: f32) -> f32 {
+        let mut value = 0.0;
+        let mut total_amplitude = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = 1.0;
+        for _ in 0..self.settings.octaves {
+            value += noise.get_noise(x * frequency, z * frequency) * amplitude;
+            total_amplitude += amplitude;
+            amplitude *= self.settings.gain;
+            frequency *= self.settings.lacunarity;
+        }
+        value / total_amplitude
+    }
+
+    // Columns of a size x size square starting at the world origin, in heightmap order
+    fn sample_columns(&self, origin: IVec3, size: i32) -> Vec<TerrainColumn> {
+        let mut columns = vec![
+            TerrainColumn {
+                height: 0,
+                biome: 0
+            };
+            (size * size) as usize
+        ];
+        let mut noise: FastNoise = FastNoise::seeded(self.seed);
+        noise.set_noise_type(self.settings.noise_type.noise_type());
+        noise.set_frequency(1.0 / self.settings.scale);
+        // Each climate map gets its own seed so they don't line up with each other
+        let temperature = climate_noise(self.seed.wrapping_add(1), CLIMATE_SCALE);
+        let humidity = climate_noise(self.seed.wrapping_add(2), CLIMATE_SCALE);
+        let continentalness = climate_noise(self.seed.wrapping_add(3), CONTINENT_SCALE);
+
+        for z in 0..size {
+            for x in 0..size {
+                // Get voxel X and Z position in global space
+                let voxel_pos = origin + IVec3::new(x, 0, z);
+                let voxel_x = voxel_pos.x as f32;
+                let voxel_z = voxel_pos.z as f32;
+                let noise_value = self.detail_noise(&noise, voxel_x, voxel_z);
+                let normalized_noise_value = (noise_value + 1.0) / 2.0;
+
+                // Fractal noise rarely leaves -0.5..0.5, stretch it to reach the biome centers
+                let climate = Climate {
+                    temperature: temperature.get_noise(voxel_x, voxel_z) * 1.5,
+                    humidity: humidity.get_noise(voxel_x, voxel_z) * 1.5,
+                    continentalness: continentalness.get_noise(voxel_x, voxel_z) * 1.5,
+                };
+                let sample = self.biomes.sample(climate, normalized_noise_value);
+                columns[(x * size + z) as usize] = TerrainColumn {
+                    height: sample.height as i32,
+                    biome: sample.biome,
+                };
+            }
+        }
+
+        columns
+    }
+
+    pub fn create_chunk_columns(&self, chunk_pos: IVec3) -> Vec<TerrainColumn> {
+        self.sample_columns(coords::chunk_origin(chunk_pos), CHUNK_SIZE)
+    }
+
+    pub fn create_chunk_heightmap(&self, chunk_pos: IVec3) -> Vec<i32> {
+        self.create_chunk_columns(chunk_pos)
+            .iter()
+            .map(|column| column.height)
+            .collect()
+    }
+
+    // Whether the ground block at world_pos is hollowed out by a cave. lowest_nearby is the
+    // lowest surface among the column and its eight neighbors.
+    fn is_cave(
+        &self,
+        noise: &CaveNoise,
+        world_pos: IVec3,
+        column: TerrainColumn,
+        lowest_nearby: i32,
+    ) -> bool {
+        let caves = self.biomes.get(column.biome).caves;
+        if column.height - world_pos.y < caves.min_depth {
+            return false;
+        }
+        // Water fills everything above the ground up to sea level, so a cave down there could
+        // open into the sea floor or into the water beside it. Keeping it a crust below the
+        // lowest surface around means it always stays dry and sealed.
+        if world_pos.y <= self.settings.sea_level && world_pos.y > lowest_nearby - CAVE_CRUST {
+            return false;
+        }
+        noise.carves(caves, world_pos)
+    }
+
+    // Top block of a column, before caves
+    fn surface_block(&self, column: TerrainColumn) -> BlockType {
+        let biome = self.biomes.get(column.biome);
+        if column.height >= self.settings.snow_line {
+            self.blocks.snow
+        } else if column.height <= self.settings.sea_level + 1 && !biome.frozen {
+            // Beaches and lake beds
+            self.blocks.sand
+        } else {
+            biome.surface
+        }
+    }
+
+    // Decoration the column's biome puts on its surface, if any. The same column always rolls
+    // the same decoration, whichever chunk asks.
+    fn column_decoration(
+        &self,
+        noise: &CaveNoise,
+        surface_pos: IVec3,
+        column: TerrainColumn,
+        lowest_nearby: i32,
+    ) -> Option<&Decoration> {
+        let biome = self.biomes.get(column.biome);
+        // Nothing grows under water, on beaches and snow, or on ground a cave took away
+        if column.height < self.settings.sea_level
+            || self.surface_block(column) != biome.surface
+            || self.is_cave(noise, surface_pos, column, lowest_nearby)
+        {
+            return None;
+        }
+        let roll = position_chance(self.seed, surface_pos * IVec3::new(1, 0, 1));
+        let mut chance_so_far = 0.0;
+        for decoration in &biome.decorations {
+            chance_so_far += decoration.chance();
+            if roll < chance_so_far {
+                return Some(decoration);
+            }
+        }
+        None
+    }
+
+    // Blocks of a decoration standing on surface_pos, in the order they are written
+    fn decoration_writes(&self, decoration: &Decoration, surface_pos: IVec3) -> Vec<FeatureWrite> {
+        let hash = position_hash(self.seed, surface_pos + IVec3::Y);
+        let write = |world_pos: IVec3, block: BlockType| FeatureWrite {
+            world_pos,
+            block,
+            replaces: BlockType::AIR,
+        };
+        let mut writes = Vec::new();
+        match decoration {
+            // Columns are built with the terrain voxels, see create_chunk_voxels
+            Decoration::Column { .. } => {}
+            &Decoration::Tree {
+                log,
+                leaves,
+                min_height,
+                max_height,
+                ..
+            } => {
+                let height = random_in(hash, min_height, max_height);
+                for y in 1..=height {
+                    writes.push(FeatureWrite {
+                        replaces: leaves,
+                        ..write(surface_pos + IVec3::Y * y, log)
+                    });
+                }
+                // Two wide layers around the top of the trunk, then two narrow ones above it
+                for dy in -2..=1 {
+                    let radius = if dy < 0 { 2 } else { 1 };
+                    for dx in -radius..=radius {
+                        for dz in -radius..=radius {
+                            let offset = IVec3::new(dx, height + dy, dz);
+                            let corner = dx.abs() == radius && dz.abs() == radius;
+                            // Round the corners off, randomly on the wide layers
+                            let trim = (hash >> ((dx + 2) * 5 + dz + 2)) & 1 == 0;
+                            if (dx == 0 && dz == 0 && dy <= 0) || corner && (dy >= 0 || trim) {
+                                continue;
+                            }
+                            writes.push(write(surface_pos + offset, leaves));
+                        }
+                    }
+                }
+            }
+            &Decoration::Boulder { block, radius, .. } => {
+                // Centered on the ground, the lower half would be hidden anyway
+                for x in -radius..=radius {
+                    for y in 1..=radius {
+                        for z in -radius..=radius {
+                            let offset = IVec3::new(x, y, z);
+                            let rough = position_chance(hash, offset) * radius as f32;
+                            if offset.as_vec3().length() <= radius as f32 + 0.5 - rough * 0.5 {
+                                writes.push(write(surface_pos + offset, block));
+                            }
+                        }
+                    }
+                }
+            }
+            Decoration::Structure { blocks, .. } => {
+                for &(offset, block) in blocks {
+                    writes.push(write(surface_pos + IVec3::Y + offset, block));
+                }
+            }
+        }
+        writes
+    }
+
+    // Builds the chunk from its padded columns, see PADDED_SIZE
+    pub fn create_chunk_voxels(
+        &self,
+        chunk_pos: IVec3,
+        padded_columns: &[TerrainColumn],
+    ) -> Vec<BlockType> {
+        let mut voxels: Vec<BlockType> = Vec::with_capacity(CHUNK_VOLUME); // vector preallocation
+        let cave_noise = CaveNoise::new(self.seed);
+        let mut columns = Vec::with_capacity(coords::CHUNK_COLUMNS);
+        let mut lowest_nearby = Vec::with_capacity(coords::CHUNK_COLUMNS);
+        let mut decorations = Vec::with_capacity(coords::CHUNK_COLUMNS);
+        for index in 0..coords::CHUNK_COLUMNS {
+            let (x, z) = coords::index_to_column(index).unwrap();
+            let column = padded_columns[padded_index(x, z)];
+            let lowest = lowest_nearby_height(padded_columns, x, z);
+            let surface_pos =
+                coords::local_to_world(chunk_pos, IVec3::new(x, 0, z)).with_y(column.height);
+            columns.push(column);
+            lowest_nearby.push(lowest);
+            let stack = match self.column_decoration(&cave_noise, surface_pos, column, lowest) {
+                Some(&Decoration::Column {
+                    block,
+                    min_height,
+                    max_height,
+                    ..
+                }) => {
+                    let hash = position_hash(self.seed, surface_pos + IVec3::Y);
+                    Some((
+                        block,
+                        column.height + random_in(hash, min_height, max_height),
+                    ))
+                }
+                _ => None,
+            };
+            decorations.push(stack);
+        }
+
+        // Voxels are pushed in storage order so Chunk::from_blocks puts them where they belong.
+        // Decorations are written per voxel too, so a cactus crossing a chunk layer comes out
+        // the same whichever of the two chunks generates first.
+        for local_pos in coords::local_positions() {
+            let column_index = coords::column_index(local_pos.x, local_pos.z).unwrap();
+            let world_pos = coords::local_to_world(chunk_pos, local_pos);
+            let voxel_y = world_pos.y;
+            let column = columns[column_index];
+            let biome = self.biomes.get(column.biome);
+            let depth = column.height - voxel_y;
+
+            // Stone continues all the way down, chunks below the surface are filled solid
+            let block_type = if depth < 0 {
+                match decorations[column_index] {
+                    Some((block, top)) if voxel_y <= top => block,
+                    _ if voxel_y == self.settings.sea_level && biome.frozen => self.blocks.ice,
+                    _ if voxel_y <= self.settings.sea_level => self.blocks.water,
+                    _ => BlockType::AIR,
+                }
+            } else if self.is_cave(&cave_noise, world_pos, column, lowest_nearby[column_index]) {
+                BlockType::AIR
+            } else if depth == 0 {
+                self.surface_block(column)
+            } else if depth <= biome.subsurface_depth {
+                biome.subsurface
+            } else {
+                self.blocks.stone
+            };
+            voxels.push(block_type);
+        }
+
+        voxels
+    }
+
+    // Trees, boulders and structures standing on the chunk's surface, including the blocks
+    // that reach into other chunks
+    pub fn create_chunk_features(
+        &self,
+        chunk_pos: IVec3,
+        padded_columns: &[TerrainColumn],
+    ) -> Vec<FeatureWrite> {
+        let cave_noise = CaveNoise::new(self.seed);
+        let mut writes = Vec::new();
+        for index in 0..coords::CHUNK_COLUMNS {
+            let (x, z) = coords::index_to_column(index).unwrap();
+            let column = padded_columns[padded_index(x, z)];
+            let surface_pos =
+                coords::local_to_world(chunk_pos, IVec3::new(x, 0, z)).with_y(column.height);
+            // Every decoration belongs to the chunk holding the ground it stands on
+            if coords::world_to_chunk(surface_pos) != chunk_pos {
+                continue;
+            }
+            let lowest = lowest_nearby_height(padded_columns, x, z);
+            if let Some(decoration) =
+                self.column_decoration(&cave_noise, surface_pos, column, lowest)
+            {
+                writes.extend(self.decoration_writes(decoration, surface_pos));
+            }
+        }
+        writes
+    }
+
+    fn padded_columns(&self, chunk_pos: IVec3) -> Vec<TerrainColumn> {
+        let origin = coords::chunk_origin(chunk_pos) - IVec3::new(1, 0, 1);
+        self.sample_columns(origin, PADDED_SIZE)
+    }
+
+    // Builds the chunk and places its decorations. Blocks landing in other chunks come back in
+    // spill, for the ChunkMap to write once those chunks are there.
+    pub fn generate(&self, chunk_pos: IVec3) -> GeneratedChunk {
+        let padded_columns = self.padded_columns(chunk_pos);
+        let mut voxels = self.create_chunk_voxels(chunk_pos, &padded_columns);
+        self.ores
+            .place_ores(self.seed, chunk_pos, self.blocks.stone, &mut voxels);
+
+        let mut spill = Vec::new();
+        for write in self.create_chunk_features(chunk_pos, &padded_columns) {
+            if coords::world_to_chunk(write.world_pos) != chunk_pos {
+                spill.push(write);
+                continue;
+            }
+            let index = coords::local_to_index(coords::world_to_local(write.world_pos)).unwrap();
+            if write.applies_to(voxels[index]) {
+                voxels[index] = write.block;
+            }
+        }
+        let heightmap: Vec<i32> = (0..coords::CHUNK_COLUMNS)
+            .map(|index| {
+                let (x, z) = coords::index_to_column(index).unwrap();
+                padded_columns[padded_index(x, z)].height
+            })
+            .collect();
+        let mut chunk = Chunk::from_blocks(&voxels);
+        self.light_chunk(chunk_pos, &heightmap, &mut chunk);
+        GeneratedChunk {
+            chunk,
+            spill,
+            from_save: false,
+        }
+    }
+
+    // Lights the chunk on its own. The chunks above aren't around on a worker thread, so the
+    // heightmap stands in for them: open sky above the ground, dimmed by the sea over it.
+    pub fn light_chunk(&self, chunk_pos: IVec3, heightmap: &[i32], chunk: &mut Chunk) {
+        let top = coords::chunk_origin(chunk_pos).y + CHUNK_HEIGHT;
+        let water_depth = (self.settings.sea_level - top + 1).max(0);
+        let water_falloff = light::falloff(&self.registry, self.blocks.water).unwrap_or(MAX_LIGHT);
+        let under_water = (MAX_LIGHT as i32 - water_depth * water_falloff as i32).max(0) as u8;
+        let sky_above: Vec<u8> = heightmap
+            .iter()
+            .map(|height| if *height >= top { 0 } else { under_water })
+            .collect();
+        chunk.set_light(light::light_chunk(chunk, &self.registry, &sky_above));
+    }
+
+    pub fn generate_chunk(&self, chunk_pos: IVec3) -> Chunk {
+        self.generate(chunk_pos).chunk
+    }
+
+    // Decoration blocks a chunk places in other chunks, without building the chunk itself.
+    // Used for chunks loaded from disk, whose neighbors may still be waiting for their trees.
+    pub fn feature_spill(&self, chunk_pos: IVec3) -> Vec<FeatureWrite> {
+        let padded_columns = self.padded_columns(chunk_pos);
+        self.create_chunk_features(chunk_pos, &padded_columns)
+            .into_iter()
+            .filter(|write| coords::world_to_chunk(write.world_pos) != chunk_pos)
+            .collect()
+    }
+}
+
+// Picks a number in min..=max from a hash
+fn random_in(hash: u64, min: i32, max: i32) -> i32 {
+    let spread = (max - min + 1).max(1) as u64;
+    min + (hash % spread) as i32
+}
+
+// Lowest surface among a column and its eight neighbors
+fn lowest_nearby_height(padded_columns: &[TerrainColumn], x: i32, z: i32) -> i32 {
+    (-1..=1)
+        .flat_map(|dx| (-1..=1).map(move |dz| (dx, dz)))
+        .map(|(dx, dz)| padded_columns[padded_index(x + dx, z + dz)].height)
+        .min()
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        block::ChunkMap,
+        save::{self, WorldSave},
+        world::load_or_generate,
+    };
+
+    #[test]
+    fn terrain_surface_follows_the_heightmap() {
+        let registry = BlockRegistry::default();
+        let mut generator = TerrainGenerator::new(42, &registry, &TerrainSettings::default());
+        // Caves may open at the surface and decorations stand on it, this only checks the
+        // height curves
+        for biome in &mut generator.biomes.biomes {
+            biome.caves = Caves::NONE;
+            biome.decorations.clear();
+        }
+        for column_pos in [IVec3::ZERO, IVec3::new(-3, 0, 5)] {
+            // Load the whole vertical stack so surfaces on any chunk layer are covered
+            let mut chunk_map = ChunkMap::with_seed(42);
+            let top_layer = coords::world_to_chunk(IVec3::splat(TERRAIN_HEIGHT as i32)).y;
+            for y in -1..=top_layer {
+                let chunk_pos = IVec3::new(column_pos.x, y, column_pos.z);
+                chunk_map.insert_chunk(chunk_pos, generator.generate_chunk(chunk_pos));
+            }
+
+            let heightmap = generator.create_chunk_heightmap(column_pos);
+            for x in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    let height = heightmap[coords::column_index(x, z).unwrap()];
+                    let surface_pos = coords::local_to_world(column_pos, IVec3::new(x, height, z));
+                    let surface = chunk_map.get_block(surface_pos).unwrap();
+                    let above = chunk_map.get_block(surface_pos + IVec3::Y).unwrap();
+                    assert!(registry.is_solid(surface), "no ground at {}", surface_pos);
+                    // Frozen sea may lie right on the ground, nothing else solid can
+                    assert!(
+                        !registry.is_solid(above) || above == generator.blocks.ice,
+                        "ground above {}",
+                        surface_pos
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mountains_rise_above_a_single_chunk() {
+        let registry = BlockRegistry::default();
+        let generator = TerrainGenerator::new(42, &registry, &TerrainSettings::default());
+        let highest = (-8..8)
+            .flat_map(|x| (-8..8).map(move |z| IVec3::new(x, 0, z)))
+            .flat_map(|chunk_pos| generator.create_chunk_heightmap(chunk_pos))
+            .max()
+            .unwrap();
+        assert!(highest > 64, "highest peak at {}", highest);
+    }
+
+    #[test]
+    fn wide_areas_cover_several_biomes() {
+        let registry = BlockRegistry::default();
+        let generator = TerrainGenerator::new(42, &registry, &TerrainSettings::default());
+        let mut seen = vec![false; generator.biomes.biomes.len()];
+        for x in -24..24 {
+            for z in -24..24 {
+                let columns = generator.create_chunk_columns(IVec3::new(x * 2, 0, z * 2));
+                for column in columns {
+                    seen[column.biome] = true;
+                }
+            }
+        }
+        let count = seen.iter().filter(|seen| **seen).count();
+        assert!(count >= 4, "only {} biomes generated", count);
+    }
+
+    #[test]
+    fn biome_heights_blend_across_borders() {
+        let registry = BlockRegistry::default();
+        let generator = TerrainGenerator::new(42, &registry, &TerrainSettings::default());
+        // Neighboring columns never jump more than a steep mountain slope, even where biomes meet
+        for chunk_x in -8..8 {
+            let columns = generator.create_chunk_columns(IVec3::new(chunk_x, 0, 0));
+            for x in 0..CHUNK_SIZE - 1 {
+                for z in 0..CHUNK_SIZE {
+                    let here = columns[coords::column_index(x, z).unwrap()];
+                    let next = columns[coords::column_index(x + 1, z).unwrap()];
+                    assert!(
+                        (here.height - next.height).abs() <= 24,
+                        "{:?} next to {:?}",
+                        here,
+                        next
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn caves_stay_dry_below_sea_level() {
+        let registry = BlockRegistry::default();
+        let generator = TerrainGenerator::new(42, &registry, &TerrainSettings::default());
+        let sea_layer = coords::world_to_chunk(IVec3::new(0, generator.settings.sea_level, 0)).y;
+        // A ring of extra chunks around the checked ones, so air at their edges has neighbors
+        let mut chunk_map = ChunkMap::with_seed(42);
+        for x in -5..5 {
+            for y in sea_layer - 2..=sea_layer + 1 {
+                for z in -5..5 {
+                    let chunk_pos = IVec3::new(x, y, z);
+                    chunk_map.insert_chunk(chunk_pos, generator.generate_chunk(chunk_pos));
+                }
+            }
+        }
+
+        let mut caves = 0;
+        let mut sea_floor = 0;
+        for x in -4..4 {
+            for y in sea_layer - 1..=sea_layer {
+                for z in -4..4 {
+                    let chunk_pos = IVec3::new(x, y, z);
+                    for local_pos in coords::local_positions() {
+                        let world_pos = coords::local_to_world(chunk_pos, local_pos);
+                        let block = chunk_map.get_block(world_pos).unwrap();
+                        if world_pos.y > generator.settings.sea_level || block != BlockType::AIR {
+                            if block == generator.blocks.water {
+                                sea_floor += 1;
+                            }
+                            continue;
+                        }
+                        // Air below sea level is always a cave, it may never touch water
+                        caves += 1;
+                        for offset in [
+                            IVec3::X,
+                            IVec3::NEG_X,
+                            IVec3::Y,
+                            IVec3::NEG_Y,
+                            IVec3::Z,
+                            IVec3::NEG_Z,
+                        ] {
+                            let neighbor = chunk_map.get_block(world_pos + offset);
+                            assert_ne!(
+                                neighbor,
+                                Some(generator.blocks.water),
+                                "cave at {} floods",
+                                world_pos
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        assert!(caves > 0, "no caves generated");
+        assert!(sea_floor > 0, "no sea in the tested area");
+    }
+
+    #[test]
+    fn decorations_cross_chunk_borders_in_any_order() {
+        let registry = BlockRegistry::default();
+        let generator = TerrainGenerator::new(42, &registry, &TerrainSettings::default());
+        let mut chunks = Vec::new();
+        for x in -3..3 {
+            for y in 0..3 {
+                for z in -3..3 {
+                    chunks.push(IVec3::new(x, y, z));
+                }
+            }
+        }
+
+        // Writes into loaded neighbors one way round, pending writes the other
+        let mut forward = ChunkMap::with_seed(42);
+        let mut spilled = 0;
+        for &chunk_pos in &chunks {
+            let generated = generator.generate(chunk_pos);
+            spilled += generated.spill.len();
+            forward.insert_generated(chunk_pos, generated);
+        }
+        let mut backward = ChunkMap::with_seed(42);
+        for &chunk_pos in chunks.iter().rev() {
+            backward.insert_generated(chunk_pos, generator.generate(chunk_pos));
+        }
+        assert!(spilled > 0, "no decoration crossed a chunk border");
+
+        let mut leaves = 0;
+        for &chunk_pos in &chunks {
+            for local_pos in coords::local_positions() {
+                let world_pos = coords::local_to_world(chunk_pos, local_pos);
+                let block = forward.get_block(world_pos);
+                assert_eq!(block, backward.get_block(world_pos), "at {}", world_pos);
+                if block == Some(generator.blocks.leaves) {
+                    leaves += 1;
+                }
+            }
+        }
+        assert!(leaves > 0, "no trees generated");
+    }
+
+    #[test]
+    fn broken_decorations_stay_broken_after_reloading() {
+        let registry = BlockRegistry::default();
+        let generator = TerrainGenerator::new(42, &registry, &TerrainSettings::default());
+        let save = WorldSave::new(std::env::temp_dir().join("kyuubic-test-broken-decorations"));
+        let _ = std::fs::remove_dir_all(&save.dir);
+
+        // A leaf a tree placed in the chunk next to its own
+        let (source, leaf_pos) = (-3..3)
+            .flat_map(|x| (0..3).flat_map(move |y| (-3..3).map(move |z| IVec3::new(x, y, z))))
+            .find_map(|chunk_pos| {
+                let leaf = generator
+                    .generate(chunk_pos)
+                    .spill
+                    .into_iter()
+                    .find(|write| write.block == generator.blocks.leaves)?;
+                Some((chunk_pos, leaf.world_pos))
+            })
+            .expect("no leaves crossed a chunk border");
+        let target = coords::world_to_chunk(leaf_pos);
+
+        let mut chunk_map = ChunkMap::with_seed(42);
+        for chunk_pos in [source, target] {
+            chunk_map.insert_generated(
+                chunk_pos,
+                load_or_generate(&save, &generator, &registry, chunk_pos),
+            );
+        }
+        assert_eq!(chunk_map.get_block(leaf_pos), Some(generator.blocks.leaves));
+        chunk_map.set_block(leaf_pos, BlockType::AIR);
+
+        // Unloaded the way streaming does it, then loaded back in either order
+        for order in [[source, target], [target, source]] {
+            let modified: Vec<IVec3> = chunk_map.modified.iter().copied().collect();
+            save::save_loaded_chunks(&save, &mut chunk_map, &registry, &modified);
+            for chunk_pos in [source, target] {
+                chunk_map.remove_chunk(chunk_pos);
+            }
+            assert!(chunk_map.feature_writes.is_empty());
+            for chunk_pos in order {
+                chunk_map.insert_generated(
+                    chunk_pos,
+                    load_or_generate(&save, &generator, &registry, chunk_pos),
+                );
+            }
+            assert_eq!(chunk_map.get_block(leaf_pos), Some(BlockType::AIR));
+        }
+        let _ = std::fs::remove_dir_all(&save.dir);
+    }
+}
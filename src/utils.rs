@@ -1,25 +1,27 @@
+use crate::{block::ChunkMap, culling::CullingStats};
 use bevy::{
     diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin},
     pbr::wireframe::WireframeConfig,
     prelude::*,
 };
 
-#[derive(Resource)]
+#[derive(Resource, Default)]
 pub struct WireframeState {
     enabled: bool,
 }
 
-impl Default for WireframeState {
-    fn default() -> Self {
-        Self { enabled: false }
-    }
-}
 #[derive(Resource, Component)]
 pub struct FpsText;
 
 #[derive(Component)]
 struct FpsRoot;
 
+#[derive(Component)]
+pub struct SeedText;
+
+#[derive(Component)]
+pub struct CullingText;
+
 pub fn update_fps(diagnostics: Res<DiagnosticsStore>, mut query: Query<&mut Text, With<FpsText>>) {
     for mut text in query.iter_mut() {
         if let Some(value) = diagnostics
@@ -67,6 +69,8 @@ pub fn setup_fps_counter(mut commands: Commands) {
                     left: Val::Auto,
                     // give it some padding for readability
                     padding: UiRect::all(Val::Px(4.0)),
+                    // stack the lines below the FPS
+                    flex_direction: FlexDirection::Column,
                     ..Default::default()
                 },
                 ..Default::default()
@@ -107,7 +111,58 @@ pub fn setup_fps_counter(mut commands: Commands) {
             },
         ))
         .id();
-    commands.entity(root).push_children(&[text_fps]);
+    // the world seed, filled in by update_seed_text once the world is loaded
+    let text_seed = commands
+        .spawn((
+            SeedText,
+            TextBundle::from_section(
+                "Seed: ",
+                TextStyle {
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ),
+        ))
+        .id();
+    // chunks drawn and hidden by cave culling
+    let text_culling = commands
+        .spawn((
+            CullingText,
+            TextBundle::from_section(
+                "Chunks: ",
+                TextStyle {
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ),
+        ))
+        .id();
+    commands
+        .entity(root)
+        .push_children(&[text_fps, text_culling, text_seed]);
+}
+
+pub fn update_seed_text(chunk_map: Res<ChunkMap>, mut query: Query<&mut Text, With<SeedText>>) {
+    let value = format!("Seed: {}", chunk_map.seed);
+    for mut text in query.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+pub fn update_culling_text(
+    stats: Res<CullingStats>,
+    mut query: Query<&mut Text, With<CullingText>>,
+) {
+    let value = format!("Chunks: {} drawn, {} culled", stats.drawn, stats.culled);
+    for mut text in query.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
 }
 
 pub fn toggle_wireframe_system(
@@ -0,0 +1,530 @@
+#![allow(dead_code)]
+use crate::{
+    atlas::{self, AtlasExtension, BlockMaterial},
+    block::ChunkMap,
+    camera::FlyCamera,
+    coords,
+    culling::ChunkConnectivity,
+    lod::{self, ChunkLods},
+    mesh::{AmbientOcclusion, ChunkMeshData, MeshData, MeshingMode},
+    registry::BlockRegistry,
+    save::{self, WorldSave},
+    settings::TerrainSettings,
+    terrain::{GeneratedChunk, TerrainGenerator},
+};
+use bevy::{
+    prelude::*,
+    tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task},
+};
+use std::collections::HashMap;
+
+// Marks an entity holding one of the meshes of a single chunk
+#[derive(Component)]
+pub struct ChunkMesh {
+    pub chunk_pos: IVec3,
+}
+
+// Chunk position -> entities holding its meshes
+#[derive(Resource, Default)]
+pub struct ChunkEntities {
+    pub map: HashMap<IVec3, Entity>,
+    // Only chunks containing water or other translucent blocks have one
+    pub translucent: HashMap<IVec3, Entity>,
+    // Vertex and index count of each chunk's current meshes
+    pub mesh_counts: HashMap<IVec3, (usize, usize)>,
+    // Face connectivity of each meshed chunk, see culling.rs
+    pub connectivity: HashMap<IVec3, ChunkConnectivity>,
+}
+
+// Materials shared by every chunk mesh
+#[derive(Resource)]
+pub struct ChunkMaterial {
+    pub opaque: Handle<BlockMaterial>,
+    // Alpha blended, the renderer sorts these entities back to front
+    pub translucent: Handle<BlockMaterial>,
+}
+
+impl FromWorld for ChunkMaterial {
+    fn from_world(world: &mut World) -> Self {
+        let registry = world.resource::<BlockRegistry>();
+        let atlas_image = atlas::build_atlas_image(registry);
+        let tile_size = 1.0 / atlas::tiles_per_row(registry.tile_count()) as f32;
+        let atlas = world.resource_mut::<Assets<Image>>().add(atlas_image);
+        let mut materials = world.resource_mut::<Assets<BlockMaterial>>();
+        let opaque = materials.add(BlockMaterial {
+            base: StandardMaterial {
+                //base_color: Color::srgb(0.8, 0.0, 0.0),
+                ..Default::default()
+            },
+            extension: AtlasExtension {
+                tile_size,
+                atlas: atlas.clone(),
+            },
+        });
+        // Water surfaces are seen from both above and below
+        let translucent = materials.add(BlockMaterial {
+            base: StandardMaterial {
+                alpha_mode: AlphaMode::Blend,
+                cull_mode: None,
+                double_sided: true,
+                ..Default::default()
+            },
+            extension: AtlasExtension { tile_size, atlas },
+        });
+        ChunkMaterial {
+            opaque,
+            translucent,
+        }
+    }
+}
+
+// Generation and meshing work running on the async compute pool.
+// Dropping a task cancels it, so unloading a chunk just removes its entries.
+#[derive(Resource, Default)]
+pub struct ChunkTasks {
+    pub generating: HashMap<IVec3, Task<GeneratedChunk>>,
+    pub meshing: HashMap<IVec3, Task<ChunkMeshData>>,
+    // Print mesh totals once the meshing queue drains
+    pub report_stats: bool,
+}
+
+// Swaps the chunk's meshes for freshly built ones, the old meshes stay visible until then
+pub fn apply_chunk_mesh(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    material: &ChunkMaterial,
+    chunk_entities: &mut ChunkEntities,
+    chunk_pos: IVec3,
+    mesh_data: ChunkMeshData,
+) {
+    chunk_entities
+        .connectivity
+        .insert(chunk_pos, mesh_data.connectivity);
+    chunk_entities.mesh_counts.insert(
+        chunk_pos,
+        (
+            mesh_data.opaque.vertices.len() + mesh_data.translucent.vertices.len(),
+            mesh_data.opaque.indices.len() + mesh_data.translucent.indices.len(),
+        ),
+    );
+    apply_mesh_layer(
+        commands,
+        meshes,
+        &material.opaque,
+        &mut chunk_entities.map,
+        chunk_pos,
+        mesh_data.opaque,
+    );
+    apply_mesh_layer(
+        commands,
+        meshes,
+        &material.translucent,
+        &mut chunk_entities.translucent,
+        chunk_pos,
+        mesh_data.translucent,
+    );
+}
+
+// Updates, spawns or despawns the entity drawing one of the chunk's meshes
+fn apply_mesh_layer(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    material: &Handle<BlockMaterial>,
+    entities: &mut HashMap<IVec3, Entity>,
+    chunk_pos: IVec3,
+    mesh_data: MeshData,
+) {
+    if mesh_data.indices.is_empty() {
+        if let Some(entity) = entities.remove(&chunk_pos) {
+            commands.entity(entity).despawn();
+        }
+        return;
+    }
+
+    let mesh_handle = meshes.add(mesh_data.into_mesh());
+    if let Some(entity) = entities.get(&chunk_pos) {
+        commands.entity(*entity).insert(mesh_handle);
+        return;
+    }
+
+    let entity = commands
+        .spawn((
+            MaterialMeshBundle {
+                mesh: mesh_handle,
+                material: material.clone(),
+                transform: Transform::from_translation(coords::chunk_origin(chunk_pos).as_vec3()),
+                ..Default::default()
+            },
+            ChunkMesh { chunk_pos },
+        ))
+        .id();
+    entities.insert(chunk_pos, entity);
+}
+
+pub fn despawn_chunk_mesh(
+    commands: &mut Commands,
+    chunk_entities: &mut ChunkEntities,
+    chunk_pos: IVec3,
+) {
+    chunk_entities.mesh_counts.remove(&chunk_pos);
+    chunk_entities.connectivity.remove(&chunk_pos);
+    for entities in [&mut chunk_entities.map, &mut chunk_entities.translucent] {
+        if let Some(entity) = entities.remove(&chunk_pos) {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+pub fn toggle_meshing_mode(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut mode: ResMut<MeshingMode>,
+    mut chunk_map: ResMut<ChunkMap>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+) {
+    if keyboard_input.just_pressed(KeyCode::KeyG) {
+        *mode = mode.next();
+        // Rebuild every chunk with the new mode and report the totals once done
+        let chunk_positions: Vec<IVec3> = chunk_map.map.keys().copied().collect();
+        for chunk_pos in chunk_positions {
+            chunk_map.mark_dirty(chunk_pos);
+        }
+        chunk_tasks.report_stats = true;
+    }
+}
+
+// O turns ambient occlusion on and off, to compare the two
+pub fn toggle_ambient_occlusion(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut ambient_occlusion: ResMut<AmbientOcclusion>,
+    mut chunk_map: ResMut<ChunkMap>,
+) {
+    if keyboard_input.just_pressed(KeyCode::KeyO) {
+        ambient_occlusion.0 = !ambient_occlusion.0;
+        println!(
+            "Ambient occlusion {}",
+            if ambient_occlusion.0 { "on" } else { "off" }
+        );
+        let chunk_positions: Vec<IVec3> = chunk_map.map.keys().copied().collect();
+        for chunk_pos in chunk_positions {
+            chunk_map.mark_dirty(chunk_pos);
+        }
+    }
+}
+
+// Controls how far around the camera chunks are kept loaded
+#[derive(Resource)]
+pub struct ChunkStreaming {
+    // Radius in chunks around the camera's chunk that gets generated and meshed
+    pub view_distance: i32,
+    // Chunk layers kept loaded above and below the camera's chunk
+    pub vertical_distance: i32,
+    // Chunks further than view_distance + unload_margin are dropped, the margin avoids
+    // reloading chunks when the camera hovers around a chunk border
+    pub unload_margin: i32,
+    // Maximum number of chunks being generated in the background at once
+    pub max_pending_chunks: usize,
+}
+
+impl Default for ChunkStreaming {
+    fn default() -> Self {
+        Self {
+            view_distance: 8,
+            vertical_distance: 3,
+            unload_margin: 2,
+            max_pending_chunks: 32,
+        }
+    }
+}
+
+fn horizontal_distance_squared(a: IVec3, b: IVec3) -> i32 {
+    let dx = a.x - b.x;
+    let dz = a.z - b.z;
+    dx * dx + dz * dz
+}
+
+impl ChunkStreaming {
+    // Chunks are loaded in a cylinder around the camera's chunk, wider than it is tall
+    fn in_range(&self, chunk_pos: IVec3, camera_chunk: IVec3, margin: i32) -> bool {
+        let distance = self.view_distance + margin;
+        horizontal_distance_squared(chunk_pos, camera_chunk) <= distance * distance
+            && (chunk_pos.y - camera_chunk.y).abs() <= self.vertical_distance + margin
+    }
+}
+
+// Queues generation of the chunks within view distance of the camera, closest first,
+// and unloads the ones out of range
+#[allow(clippy::too_many_arguments)]
+pub fn stream_chunks(
+    mut commands: Commands,
+    mut chunk_map: ResMut<ChunkMap>,
+    mut chunk_entities: ResMut<ChunkEntities>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    streaming: Res<ChunkStreaming>,
+    save: Res<WorldSave>,
+    registry: Res<BlockRegistry>,
+    settings: Res<TerrainSettings>,
+    camera_query: Query<&FlyCamera>,
+) {
+    let camera = match camera_query.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let camera_chunk = coords::point_to_chunk(camera.get_position());
+
+    let in_range =
+        |chunk_pos: &IVec3| streaming.in_range(*chunk_pos, camera_chunk, streaming.unload_margin);
+    let out_of_range: Vec<IVec3> = chunk_map
+        .map
+        .keys()
+        .filter(|chunk_pos| !in_range(chunk_pos))
+        .copied()
+        .collect();
+    // Edited chunks would be regenerated from scratch next time, write them out first
+    let modified: Vec<IVec3> = out_of_range
+        .iter()
+        .filter(|chunk_pos| chunk_map.modified.contains(chunk_pos))
+        .copied()
+        .collect();
+    if !modified.is_empty() {
+        save::save_loaded_chunks(&save, &mut chunk_map, &registry, &modified);
+    }
+    // Chunks whose save failed stay loaded, so their edits aren't lost and the save is retried
+    for chunk_pos in out_of_range {
+        if chunk_map.modified.contains(&chunk_pos) {
+            continue;
+        }
+        chunk_map.remove_chunk(chunk_pos);
+        chunk_tasks.meshing.remove(&chunk_pos);
+        despawn_chunk_mesh(&mut commands, &mut chunk_entities, chunk_pos);
+    }
+    chunk_tasks
+        .generating
+        .retain(|chunk_pos, _| in_range(chunk_pos));
+
+    let free_slots = streaming
+        .max_pending_chunks
+        .saturating_sub(chunk_tasks.generating.len());
+    if free_slots == 0 {
+        return;
+    }
+
+    let view_distance = streaming.view_distance;
+    let vertical_distance = streaming.vertical_distance;
+    let mut missing: Vec<IVec3> = Vec::new();
+    for z in -view_distance..=view_distance {
+        for y in -vertical_distance..=vertical_distance {
+            for x in -view_distance..=view_distance {
+                let chunk_pos = camera_chunk + IVec3::new(x, y, z);
+                if streaming.in_range(chunk_pos, camera_chunk, 0)
+                    && chunk_map.get_chunk(chunk_pos).is_none()
+                    && !chunk_tasks.generating.contains_key(&chunk_pos)
+                {
+                    missing.push(chunk_pos);
+                }
+            }
+        }
+    }
+    missing.sort_by_key(|chunk_pos| chunk_pos.distance_squared(camera_chunk));
+
+    let task_pool = AsyncComputeTaskPool::get();
+    for chunk_pos in missing.into_iter().take(free_slots) {
+        let generator = chunk_map.generator(&registry, &settings);
+        let save = save.clone();
+        let registry = registry.clone();
+        let task = task_pool
+            .spawn(async move { load_or_generate(&save, &generator, &registry, chunk_pos) });
+        chunk_tasks.generating.insert(chunk_pos, task);
+    }
+}
+
+// Saved chunks keep their edits, everything else is generated from the seed
+pub fn load_or_generate(
+    save: &WorldSave,
+    generator: &TerrainGenerator,
+    registry: &BlockRegistry,
+    chunk_pos: IVec3,
+) -> GeneratedChunk {
+    match save.load_chunk(chunk_pos, generator.seed, registry) {
+        Ok(Some(mut chunk)) => {
+            let heightmap = generator.create_chunk_heightmap(chunk_pos);
+            generator.light_chunk(chunk_pos, &heightmap, &mut chunk);
+            GeneratedChunk {
+                chunk,
+                spill: generator.feature_spill(chunk_pos),
+                from_save: true,
+            }
+        }
+        Ok(None) => generator.generate(chunk_pos),
+        Err(err) => {
+            println!("Failed to load chunk {}: {}", chunk_pos, err);
+            generator.generate(chunk_pos)
+        }
+    }
+}
+
+// Throws away the loaded terrain when the terrain settings change, so streaming builds it again
+// with the new ones. Edited chunks are saved first and come back from disk as they were, the
+// ones that failed to save are kept as they are.
+pub fn regenerate_terrain(
+    mut commands: Commands,
+    mut chunk_map: ResMut<ChunkMap>,
+    mut chunk_entities: ResMut<ChunkEntities>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    save: Res<WorldSave>,
+    registry: Res<BlockRegistry>,
+    settings: Res<TerrainSettings>,
+) {
+    if !settings.is_changed() || settings.is_added() {
+        return;
+    }
+    let modified: Vec<IVec3> = chunk_map.modified.iter().copied().collect();
+    if !modified.is_empty() {
+        save::save_loaded_chunks(&save, &mut chunk_map, &registry, &modified);
+    }
+    let loaded: Vec<IVec3> = chunk_map
+        .map
+        .keys()
+        .filter(|chunk_pos| !chunk_map.modified.contains(chunk_pos))
+        .copied()
+        .collect();
+    println!(
+        "Terrain settings changed, regenerating {} chunks",
+        loaded.len()
+    );
+    for chunk_pos in loaded {
+        chunk_map.remove_chunk(chunk_pos);
+        despawn_chunk_mesh(&mut commands, &mut chunk_entities, chunk_pos);
+    }
+    chunk_tasks.generating.clear();
+    chunk_tasks.meshing.clear();
+}
+
+// Moves finished chunks into the ChunkMap and flags them and their loaded neighbors for meshing
+pub fn poll_generation_tasks(mut chunk_map: ResMut<ChunkMap>, mut chunk_tasks: ResMut<ChunkTasks>) {
+    let mut finished: Vec<(IVec3, GeneratedChunk)> = Vec::new();
+    chunk_tasks
+        .generating
+        .retain(|chunk_pos, task| match block_on(future::poll_once(task)) {
+            Some(generated) => {
+                finished.push((*chunk_pos, generated));
+                false
+            }
+            None => true,
+        });
+
+    for (chunk_pos, generated) in finished {
+        chunk_map.insert_generated(chunk_pos, generated);
+        chunk_map.mark_dirty(chunk_pos);
+        // Loaded neighbors emitted faces against the missing chunk, rebuild them to close the seam
+        for offset in [
+            IVec3::X,
+            IVec3::NEG_X,
+            IVec3::Y,
+            IVec3::NEG_Y,
+            IVec3::Z,
+            IVec3::NEG_Z,
+        ] {
+            chunk_map.mark_dirty(chunk_pos + offset);
+        }
+    }
+}
+
+// Starts a meshing task for every dirty chunk, replacing any outdated task still running for it
+pub fn queue_mesh_tasks(
+    mut chunk_map: ResMut<ChunkMap>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    meshing_mode: Res<MeshingMode>,
+    ambient_occlusion: Res<AmbientOcclusion>,
+    lods: Res<ChunkLods>,
+    registry: Res<BlockRegistry>,
+) {
+    if chunk_map.dirty.is_empty() {
+        return;
+    }
+
+    let task_pool = AsyncComputeTaskPool::get();
+    let mode = *meshing_mode;
+    let ambient_occlusion = *ambient_occlusion;
+    let dirty: Vec<IVec3> = chunk_map.dirty.drain().collect();
+    for chunk_pos in dirty {
+        let neighborhood = match chunk_map.neighborhood(chunk_pos) {
+            Some(neighborhood) => neighborhood,
+            None => continue,
+        };
+        let registry = registry.clone();
+        let level = lods.level(chunk_pos);
+        let neighbor_levels = lod::neighbor_levels(&lods, chunk_pos);
+        let task = task_pool.spawn(async move {
+            lod::generate_lod_mesh(
+                &neighborhood,
+                &registry,
+                mode,
+                ambient_occlusion,
+                level,
+                &neighbor_levels,
+            )
+        });
+        chunk_tasks.meshing.insert(chunk_pos, task);
+    }
+}
+
+// Uploads finished chunk meshes
+pub fn poll_mesh_tasks(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut chunk_entities: ResMut<ChunkEntities>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    chunk_material: Res<ChunkMaterial>,
+    meshing_mode: Res<MeshingMode>,
+) {
+    let mut finished: Vec<(IVec3, ChunkMeshData)> = Vec::new();
+    chunk_tasks
+        .meshing
+        .retain(|chunk_pos, task| match block_on(future::poll_once(task)) {
+            Some(mesh_data) => {
+                finished.push((*chunk_pos, mesh_data));
+                false
+            }
+            None => true,
+        });
+
+    for (chunk_pos, mesh_data) in finished {
+        apply_chunk_mesh(
+            &mut commands,
+            &mut meshes,
+            &chunk_material,
+            &mut chunk_entities,
+            chunk_pos,
+            mesh_data,
+        );
+    }
+
+    if chunk_tasks.report_stats && chunk_tasks.meshing.is_empty() {
+        chunk_tasks.report_stats = false;
+        let (vertex_count, index_count) = chunk_entities
+            .mesh_counts
+            .values()
+            .fold((0, 0), |(v, i), (vertices, indices)| {
+                (v + vertices, i + indices)
+            });
+        println!(
+            "{:?} meshing: {} vertices, {} indices",
+            *meshing_mode, vertex_count, index_count
+        );
+    }
+}
+
+pub fn print_memory_stats(keyboard_input: Res<ButtonInput<KeyCode>>, chunk_map: Res<ChunkMap>) {
+    if keyboard_input.just_pressed(KeyCode::KeyM) {
+        let stats = chunk_map.memory_stats();
+        let saved = stats.unpacked_bytes.saturating_sub(stats.used_bytes);
+        println!(
+            "Chunks: {} ({} uniform), voxel memory: {} KiB, flat Vec<Voxel>: {} KiB, saved: {} KiB",
+            stats.chunks,
+            stats.uniform_chunks,
+            stats.used_bytes / 1024,
+            stats.unpacked_bytes / 1024,
+            saved / 1024
+        );
+    }
+}